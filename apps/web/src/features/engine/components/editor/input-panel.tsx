@@ -44,6 +44,7 @@ import { toastSuccess } from "~utils/toast";
 import {
   currentAllResolvedDependenciesAtom,
   currentTableDependenciesAtom,
+  currentTableDiagnosticsAtom,
   currentTableHashAtom,
   currentTableMetadataAtom,
   editorStatusAtom,
@@ -72,6 +73,8 @@ type Props = {
 
 class MissingDependenciesError extends Error {}
 
+class ParseDiagnosticsError extends Error {}
+
 export function InputPanel({
   tableIdentifier,
   inputRef,
@@ -92,6 +95,7 @@ export function InputPanel({
   const setEditorStatus = useSetAtom(editorStatusAtom);
   const setRollResults = useSetAtom(rollHistoryAtom);
   const setTableMetadata = useSetAtom(currentTableMetadataAtom);
+  const setTableDiagnostics = useSetAtom(currentTableDiagnosticsAtom);
   const [wantedDependencyIdentifiers, setWantedDependencyIdentifiers] = useAtom(
     currentTableDependenciesAtom,
   );
@@ -120,18 +124,46 @@ export function InputPanel({
         if (!value) {
           setTableHash(null);
           setTableMetadata([]);
+          setTableDiagnostics([]);
         } else {
           setEditorStatus("parsing");
 
+          const trimmedValue = value.trim();
           const valueWithDependencies = injectNamespacePragmasWorkaround(
-            value.trim(),
+            trimmedValue,
             updatedResolvedDependencies,
           );
 
-          const { hash, metadata, dependencies, missingDependencies } =
-            await workerInstance.parse(valueWithDependencies);
+          const {
+            hash,
+            metadata,
+            dependencies,
+            missingDependencies,
+            diagnostics,
+          } = await workerInstance.parse(valueWithDependencies);
+
+          // dependencies are appended after the definition, so anything past
+          // it isn't something the user can fix here
+          const ownDiagnostics = diagnostics.filter(
+            (d) => d.offset <= trimmedValue.length,
+          );
 
           setWantedDependencyIdentifiers(dependencies);
+          setTableDiagnostics(ownDiagnostics);
+
+          // broken tables are skipped, the rest are still listed
+          setTableHash(hash);
+          setTableMetadata(metadata);
+
+          const errors = ownDiagnostics.filter((d) => d.severity === "error");
+
+          if (errors.length > 0) {
+            throw new ParseDiagnosticsError(
+              errors
+                .map((d) => `${d.line}:${d.column}: ${d.message}`)
+                .join("\n"),
+            );
+          }
 
           if (missingDependencies.length > 0) {
             throw new MissingDependenciesError(
@@ -140,8 +172,6 @@ export function InputPanel({
           }
 
           setEditorStatus("valid");
-          setTableHash(hash);
-          setTableMetadata(metadata);
 
           availableTables = metadata
             .filter((m) => m.namespace === undefined)
@@ -168,6 +198,7 @@ export function InputPanel({
       setWantedDependencyIdentifiers,
       setTableHash,
       setTableMetadata,
+      setTableDiagnostics,
       onParseError,
     ],
   );
@@ -1,4 +1,8 @@
-import type { RollResult, TableMetadata } from "@manifold/lib/models/roll";
+import type {
+  Diagnostic,
+  RollResult,
+  TableMetadata,
+} from "@manifold/lib/models/roll";
 import type { RouterOutput } from "@manifold/router";
 import { atom } from "jotai";
 
@@ -33,6 +37,8 @@ export const directDependencyVersionsAtom = atom<
 
 export const currentTableMetadataAtom = atom<TableMetadata[]>([]);
 
+export const currentTableDiagnosticsAtom = atom<Diagnostic[]>([]);
+
 export const exportedOnlyAtom = atom(true);
 
 export const visibleTableMetadataAtom = atom((get) => {
@@ -1,7 +1,11 @@
 /// <reference lib="webworker" />
 
 import init, { table_hash, TableCollection } from "@manifold/engine";
-import type { TableMetadata } from "@manifold/lib/models/roll";
+import type {
+  Diagnostic,
+  TableMetadata,
+  TracedResult,
+} from "@manifold/lib/models/roll";
 import { LRUCache } from "lru-cache";
 
 // eslint-disable-next-line @typescript-eslint/no-unused-vars
@@ -22,6 +26,7 @@ function afterInit<U extends unknown[], R>(f: (...args: U) => R) {
 
 /**
  * Parses text with table definitions and instantiates a TableCollection. Caches the result.
+ * Broken tables are skipped rather than failing the parse, and reported in `diagnostics`.
  */
 export const parse = afterInit((text: string) => {
   // compute hash based on table definition text, cache for later
@@ -43,11 +48,12 @@ export const parse = afterInit((text: string) => {
       metadata: tableCollection.table_metadata() as TableMetadata[],
       dependencies: tableCollection.dependencies() as string[],
       missingDependencies: tableCollection.validate_tables() as string[],
+      diagnostics: tableCollection.diagnostics() as Diagnostic[],
     };
   }
 
   // otherwise, create a new instance and cache it
-  const tableCollection = new TableCollection(text);
+  const tableCollection = TableCollection.parseRecovering(text);
 
   hashToTableCollection.set(hash, tableCollection);
 
@@ -56,6 +62,7 @@ export const parse = afterInit((text: string) => {
     metadata: tableCollection.table_metadata() as TableMetadata[],
     dependencies: tableCollection.dependencies() as string[],
     missingDependencies: tableCollection.validate_tables() as string[],
+    diagnostics: tableCollection.diagnostics() as Diagnostic[],
   };
 });
 
@@ -77,3 +84,20 @@ export const gen = afterInit((hash: string, text: string, tableId: string) => {
 
   return tabol.gen(tableId, false);
 });
+
+/**
+ * Like `gen`, but also returns the trace of table rolls, dice and filters
+ * that produced the result.
+ */
+export const genTraced = afterInit(
+  (hash: string, text: string, tableId: string) => {
+    if (!hashToTableCollection.has(hash)) {
+      parse(text);
+    }
+
+    // eslint-disable-next-line @typescript-eslint/no-non-null-assertion
+    const tabol = hashToTableCollection.get(hash)!;
+
+    return tabol.gen_traced(tableId) as TracedResult;
+  },
+);
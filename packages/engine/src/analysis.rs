@@ -0,0 +1,988 @@
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use crate::custom_filter::CustomFilters;
+use crate::dice::{Dice, DiceExpr};
+use crate::table_collection::{
+    count_filter, join_results, Condition, FilterOp, FilterStages, GenContext, Rule, RuleInst,
+    TableCollection, TableDefinition, TableError,
+};
+
+/**
+ * The most distinct outcomes computed for a single rule. Rules that could
+ * produce more than this (e.g. `{50d100} {50d100}`) report no outcomes.
+ */
+static OUTCOME_LIMIT: usize = 10_000;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TableAnalysis {
+    pub table_id: String,
+    pub rules: Vec<RuleAnalysis>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RuleAnalysis {
+    pub index: usize,
+    pub raw: String,
+    pub weight: f32,
+    // chance of this rule being selected
+    pub probability: f64,
+    // every possible output of the rule with its chance given the rule was
+    // selected, most likely first. `None` when the rule interpolates another
+    // table or has too many outcomes to list
+    pub outcomes: Option<Vec<Outcome>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Enumeration {
+    pub table_id: String,
+    // upper bound on the number of distinct outputs, infinite when the table
+    // can interpolate itself or repeat a result without limit
+    pub size_estimate: f64,
+    // every distinct output with its probability, most likely first. `None`
+    // when there are too many outputs to list
+    pub outcomes: Option<Vec<Outcome>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Outcome {
+    pub text: String,
+    pub probability: f64,
+}
+
+pub fn analyze_table(table: &TableDefinition, custom: &CustomFilters) -> TableAnalysis {
+    let total: f64 = table.rules.iter().map(|r| r.weight as f64).sum();
+    let ctx = filter_context(table);
+
+    TableAnalysis {
+        table_id: table.id.clone(),
+        rules: table
+            .rules
+            .iter()
+            .enumerate()
+            .map(|(index, rule)| RuleAnalysis {
+                index,
+                raw: rule.raw.clone(),
+                weight: rule.weight,
+                probability: rule.weight as f64 / total,
+                outcomes: rule_outcomes(rule, custom, &ctx),
+            })
+            .collect(),
+    }
+}
+
+/**
+ * Combines the outcomes of each part of the rule, in order, concatenating
+ * their text and multiplying their probabilities. Outcomes with the same
+ * text are merged.
+ */
+fn rule_outcomes(rule: &Rule, custom: &CustomFilters, ctx: &GenContext) -> Option<Vec<Outcome>> {
+    let mut outcomes = vec![Outcome {
+        text: String::new(),
+        probability: 1.0,
+    }];
+
+    for part in rule.parts.iter() {
+        let part_outcomes: Vec<Outcome> = match part.unbound() {
+            RuleInst::Literal(text) => vec![Outcome {
+                text: text.clone(),
+                probability: 1.0,
+            }],
+            RuleInst::DiceRoll(expr, filters) => {
+                let items = expr_distribution(expr)?
+                    .into_iter()
+                    .map(|(sum, probability)| Outcome {
+                        text: sum.to_string(),
+                        probability,
+                    })
+                    .collect();
+
+                filtered_distribution(items, filters, custom, ctx)?
+            }
+            RuleInst::Choice(options, filters) => {
+                let mut items = vec![];
+
+                for (option, probability) in option_probabilities(options) {
+                    items.extend(rule_outcomes(option, custom, ctx)?.into_iter().map(|o| {
+                        Outcome {
+                            text: o.text,
+                            probability: o.probability * probability,
+                        }
+                    }));
+                }
+
+                let items = merge_outcomes(items);
+
+                if count_size(items.len() as f64, filters) > OUTCOME_LIMIT as f64 {
+                    return None;
+                }
+
+                filtered_distribution(items, filters, custom, ctx)?
+            }
+            RuleInst::Conditional(condition, then, otherwise) => {
+                let mut items = vec![];
+
+                for (branch, probability) in branch_probabilities(condition, then, otherwise)? {
+                    let branch_outcomes = match branch {
+                        Some(rule) => rule_outcomes(rule, custom, ctx)?,
+                        None => vec![Outcome {
+                            text: String::new(),
+                            probability: 1.0,
+                        }],
+                    };
+
+                    items.extend(branch_outcomes.into_iter().map(|o| Outcome {
+                        text: o.text,
+                        probability: o.probability * probability,
+                    }));
+                }
+
+                merge_outcomes(items)
+            }
+            RuleInst::Interpolation(..)
+            | RuleInst::ExternalInterpolation(..)
+            | RuleInst::Variable(..) => return None,
+            RuleInst::Bind(..) => unreachable!("`unbound` never returns a binding"),
+        };
+
+        if outcomes.len() * part_outcomes.len() > OUTCOME_LIMIT {
+            return None;
+        }
+
+        outcomes = concat_outcomes(&outcomes, &part_outcomes);
+    }
+
+    Some(merge_outcomes(outcomes))
+}
+
+/**
+ * Every combination of an outcome from `a` followed by one from `b`.
+ */
+fn concat_outcomes(a: &[Outcome], b: &[Outcome]) -> Vec<Outcome> {
+    a.iter()
+        .flat_map(|a| {
+            b.iter().map(|b| Outcome {
+                text: format!("{}{}", a.text, b.text),
+                probability: a.probability * b.probability,
+            })
+        })
+        .collect()
+}
+
+pub fn enumerate_table(tables: &TableCollection, id: &str) -> Result<Enumeration, TableError> {
+    if tables.table(id).is_none() {
+        return Err(TableError::CallError(format!(
+            "No table found with id {}",
+            id
+        )));
+    }
+
+    let mut enumerator = Enumerator::new(tables);
+
+    let size_estimate = enumerator.table_size(id)?;
+    let outcomes = if size_estimate <= OUTCOME_LIMIT as f64 {
+        let outcomes = enumerator.table_distribution(id)?;
+
+        (!enumerator.incomplete).then_some(outcomes)
+    } else {
+        None
+    };
+
+    Ok(Enumeration {
+        table_id: id.to_string(),
+        size_estimate,
+        outcomes,
+    })
+}
+
+/**
+ * The number of distinct results `part`, an interpolation or inline choice in
+ * a rule of `table`, can draw from for its `unique(N)` filter, i.e. after the
+ * filters before it. `None` when there are too many to count or they depend
+ * on a variable.
+ */
+pub fn distinct_results(
+    tables: &TableCollection,
+    table: &TableDefinition,
+    part: &RuleInst,
+) -> Result<Option<usize>, TableError> {
+    let mut enumerator = Enumerator::new(tables);
+    enumerator.ctx.inflections = table.inflections.clone();
+
+    let (size, filters) = match part {
+        RuleInst::Interpolation(id, filters)
+        | RuleInst::ExternalInterpolation(_, _, id, filters) => {
+            (enumerator.table_size(id)?, filters)
+        }
+        RuleInst::Choice(options, filters) => {
+            let mut size = 0.0;
+
+            for option in options.iter() {
+                size += enumerator.rule_size(option)?;
+            }
+
+            (size, filters)
+        }
+        _ => return Ok(None),
+    };
+
+    if size > OUTCOME_LIMIT as f64 {
+        return Ok(None);
+    }
+
+    let items = match part {
+        RuleInst::Choice(options, _) => enumerator.choice_distribution(options)?,
+        RuleInst::Interpolation(id, _) | RuleInst::ExternalInterpolation(_, _, id, _) => {
+            enumerator.table_distribution(id)?
+        }
+        _ => unreachable!("only interpolations and choices are sized above"),
+    };
+
+    if enumerator.incomplete {
+        return Ok(None);
+    }
+
+    let mut distinct = HashSet::new();
+
+    for mut item in items {
+        for filter in FilterStages::new(filters).each {
+            if filter
+                .apply(&mut item.text, tables.custom_filters(), &enumerator.ctx)
+                .is_err()
+            {
+                return Ok(None);
+            }
+        }
+
+        distinct.insert(item.text);
+    }
+
+    Ok(Some(distinct.len()))
+}
+
+/**
+ * Expands tables into their full output distributions, caching each table
+ * since the same table is often interpolated from many rules.
+ */
+struct Enumerator<'a> {
+    tables: &'a TableCollection,
+    sizes: HashMap<String, f64>,
+    distributions: HashMap<String, Vec<Outcome>>,
+    // tables currently being sized, to detect recursion
+    in_progress: HashSet<String>,
+    // set when some dice expression's distribution couldn't be computed
+    incomplete: bool,
+    // for applying filters, with the inflections of the table being expanded
+    ctx: GenContext,
+}
+
+impl<'a> Enumerator<'a> {
+    fn new(tables: &'a TableCollection) -> Self {
+        Self {
+            tables,
+            sizes: HashMap::new(),
+            distributions: HashMap::new(),
+            in_progress: HashSet::new(),
+            incomplete: false,
+            ctx: GenContext::new(Some(0)),
+        }
+    }
+
+    fn table(&self, id: &str) -> Result<&'a TableDefinition, TableError> {
+        self.tables
+            .table(id)
+            .ok_or(TableError::MissingDependencyError(
+                format!("Missing dependency with id {}", id),
+                id.to_string(),
+            ))
+    }
+
+    /**
+     * Estimates the number of distinct outputs of a table by assuming every
+     * combination of parts produces a different string.
+     */
+    fn table_size(&mut self, id: &str) -> Result<f64, TableError> {
+        if let Some(size) = self.sizes.get(id) {
+            return Ok(*size);
+        }
+
+        if self.in_progress.contains(id) {
+            return Ok(f64::INFINITY);
+        }
+
+        self.in_progress.insert(id.to_string());
+
+        let mut size = 0.0;
+
+        for rule in self.table(id)?.rules.iter().filter(|r| r.weight > 0.0) {
+            size += self.rule_size(rule)?;
+        }
+
+        self.in_progress.remove(id);
+        self.sizes.insert(id.to_string(), size);
+
+        Ok(size)
+    }
+
+    fn rule_size(&mut self, rule: &Rule) -> Result<f64, TableError> {
+        let mut size = 1.0;
+
+        for part in rule.parts.iter() {
+            size *= match part.unbound() {
+                // repeats a value that's already counted
+                RuleInst::Literal(_) | RuleInst::Variable(..) => 1.0,
+                RuleInst::DiceRoll(expr, _) => expr_size(expr),
+                RuleInst::Interpolation(id, filters)
+                | RuleInst::ExternalInterpolation(_, _, id, filters) => {
+                    count_size(self.table_size(id)?, filters)
+                }
+                RuleInst::Choice(options, filters) => {
+                    let mut options_size = 0.0;
+
+                    for option in options.iter() {
+                        options_size += self.rule_size(option)?;
+                    }
+
+                    count_size(options_size, filters)
+                }
+                RuleInst::Conditional(_, then, otherwise) => {
+                    let otherwise_size = match otherwise {
+                        Some(otherwise) => self.rule_size(otherwise)?,
+                        None => 1.0,
+                    };
+
+                    self.rule_size(then)? + otherwise_size
+                }
+                RuleInst::Bind(..) => unreachable!("`unbound` never returns a binding"),
+            };
+        }
+
+        Ok(size)
+    }
+
+    /**
+     * The distribution of a table's outputs. Only called once `table_size`
+     * has confirmed the table is finite.
+     */
+    fn table_distribution(&mut self, id: &str) -> Result<Vec<Outcome>, TableError> {
+        if let Some(outcomes) = self.distributions.get(id) {
+            return Ok(outcomes.clone());
+        }
+
+        let table = self.table(id)?;
+        let total: f64 = table.rules.iter().map(|r| r.weight as f64).sum();
+        let inflections = std::mem::replace(&mut self.ctx.inflections, table.inflections.clone());
+        let mut outcomes = vec![];
+
+        for rule in table.rules.iter().filter(|r| r.weight > 0.0) {
+            let probability = rule.weight as f64 / total;
+
+            outcomes.extend(self.rule_distribution(rule)?.into_iter().map(|o| Outcome {
+                text: o.text,
+                probability: o.probability * probability,
+            }));
+        }
+
+        self.ctx.inflections = inflections;
+
+        let outcomes = merge_outcomes(outcomes);
+        self.distributions.insert(id.to_string(), outcomes.clone());
+
+        Ok(outcomes)
+    }
+
+    fn rule_distribution(&mut self, rule: &Rule) -> Result<Vec<Outcome>, TableError> {
+        let mut outcomes = vec![Outcome {
+            text: String::new(),
+            probability: 1.0,
+        }];
+
+        for part in rule.parts.iter() {
+            let part_outcomes = match part.unbound() {
+                RuleInst::Literal(text) => vec![Outcome {
+                    text: text.clone(),
+                    probability: 1.0,
+                }],
+                RuleInst::DiceRoll(expr, filters) => {
+                    let items = expr_distribution(expr)
+                        .unwrap_or_else(|| {
+                            // e.g. `{10d10kh3}`, few outputs but too many rolls
+                            // to compute them
+                            self.incomplete = true;
+                            vec![]
+                        })
+                        .into_iter()
+                        .map(|(sum, probability)| Outcome {
+                            text: sum.to_string(),
+                            probability,
+                        })
+                        .collect();
+
+                    self.filtered_distribution(items, filters)
+                }
+                RuleInst::Interpolation(id, filters)
+                | RuleInst::ExternalInterpolation(_, _, id, filters) => {
+                    self.interpolation_distribution(id, filters)?
+                }
+                RuleInst::Choice(options, filters) => {
+                    let items = self.choice_distribution(options)?;
+
+                    self.filtered_distribution(items, filters)
+                }
+                RuleInst::Conditional(condition, then, otherwise) => {
+                    let Some(branches) = branch_probabilities(condition, then, otherwise) else {
+                        // depends on a variable bound earlier, see `Variable`
+                        self.incomplete = true;
+                        continue;
+                    };
+
+                    let mut items = vec![];
+
+                    for (branch, probability) in branches {
+                        let branch_outcomes = match branch {
+                            Some(rule) => self.rule_distribution(rule)?,
+                            None => vec![Outcome {
+                                text: String::new(),
+                                probability: 1.0,
+                            }],
+                        };
+
+                        items.extend(branch_outcomes.into_iter().map(|o| Outcome {
+                            text: o.text,
+                            probability: o.probability * probability,
+                        }));
+                    }
+
+                    merge_outcomes(items)
+                }
+                RuleInst::Variable(..) => {
+                    // the value depends on which outcome was bound earlier,
+                    // which these distributions don't keep track of
+                    self.incomplete = true;
+                    vec![]
+                }
+                RuleInst::Bind(..) => unreachable!("`unbound` never returns a binding"),
+            };
+
+            outcomes = merge_outcomes(concat_outcomes(&outcomes, &part_outcomes));
+        }
+
+        Ok(outcomes)
+    }
+
+    /**
+     * The distribution of an inline choice's options, before its filters.
+     */
+    fn choice_distribution(&mut self, options: &[Rule]) -> Result<Vec<Outcome>, TableError> {
+        let mut items = vec![];
+
+        for (option, probability) in option_probabilities(options) {
+            items.extend(
+                self.rule_distribution(option)?
+                    .into_iter()
+                    .map(|o| Outcome {
+                        text: o.text,
+                        probability: o.probability * probability,
+                    }),
+            );
+        }
+
+        Ok(merge_outcomes(items))
+    }
+
+    fn interpolation_distribution(
+        &mut self,
+        id: &str,
+        filters: &[FilterOp],
+    ) -> Result<Vec<Outcome>, TableError> {
+        let items = self.table_distribution(id)?;
+
+        Ok(self.filtered_distribution(items, filters))
+    }
+
+    fn filtered_distribution(&mut self, items: Vec<Outcome>, filters: &[FilterOp]) -> Vec<Outcome> {
+        filtered_distribution(items, filters, self.tables.custom_filters(), &self.ctx)
+            .unwrap_or_else(|| {
+                // e.g. `repeat(3d6!)` or `count($n)`, see `DiceRoll` and `Variable`
+                self.incomplete = true;
+                vec![]
+            })
+    }
+}
+
+/**
+ * A context for applying the filters in a table's rules outside of
+ * generation. No variables are bound.
+ */
+fn filter_context(table: &TableDefinition) -> GenContext {
+    let mut ctx = GenContext::new(Some(0));
+    ctx.inflections = table.inflections.clone();
+    ctx
+}
+
+/**
+ * Each option of an inline choice with its chance of being picked.
+ */
+fn option_probabilities(options: &[Rule]) -> impl Iterator<Item = (&Rule, f64)> {
+    let total: f64 = options.iter().map(|o| o.weight as f64).sum();
+
+    options.iter().map(move |o| (o, o.weight as f64 / total))
+}
+
+/**
+ * Each branch of a conditional that can be taken with its chance, `None` for
+ * an empty `else`. Only chances can be computed, comparisons depend on which
+ * value was bound.
+ */
+fn branch_probabilities<'a>(
+    condition: &Condition,
+    then: &'a Rule,
+    otherwise: &'a Option<Rule>,
+) -> Option<Vec<(Option<&'a Rule>, f64)>> {
+    match condition {
+        Condition::Chance(chance) => {
+            let chance = *chance as f64 / 100.0;
+            let branches = [(Some(then), chance), (otherwise.as_ref(), 1.0 - chance)];
+
+            Some(branches.into_iter().filter(|(_, p)| *p > 0.0).collect())
+        }
+        Condition::Equals(..) | Condition::NotEquals(..) => None,
+    }
+}
+
+/**
+ * Mirrors `Rule::resolve_filtered`: filters are applied to each result,
+ * `unique(N)` draws N distinct results (i.e. without replacement),
+ * `repeat(N)` draws N results with replacement and `join` combines them,
+ * before the filters after it apply to the joined text. `None` when the
+ * distribution of N can't be computed, or a filter depends on a variable.
+ */
+fn filtered_distribution(
+    items: Vec<Outcome>,
+    filters: &[FilterOp],
+    custom: &CustomFilters,
+    ctx: &GenContext,
+) -> Option<Vec<Outcome>> {
+    let stages = FilterStages::new(filters);
+    let mut filtered = vec![];
+
+    for mut item in items {
+        for filter in stages.each {
+            filter.apply(&mut item.text, custom, ctx).ok()?;
+        }
+
+        filtered.push(item);
+    }
+
+    let items = merge_outcomes(filtered);
+    let (separator, conjunction) = stages.join;
+
+    let (counts, unique) = match stages.count {
+        Some((expr, unique)) => {
+            let mut counts: BTreeMap<usize, f64> = BTreeMap::new();
+
+            for (n, p) in expr_distribution(expr)? {
+                *counts.entry(n.max(0) as usize).or_default() += p;
+            }
+
+            (counts, unique)
+        }
+        None => (BTreeMap::from([(1, 1.0)]), true),
+    };
+
+    let max_count = counts.keys().last().copied().unwrap_or(0);
+    let mut sequences: Vec<(Vec<usize>, f64)> = vec![(vec![], 1.0)];
+    let mut outcomes = vec![];
+
+    // extend every sequence by one result at a time, collecting the
+    // sequences of each length that N can be
+    for length in 0..=max_count {
+        if let Some(chance) = counts.get(&length) {
+            outcomes.extend(sequences.iter().map(|(sequence, probability)| Outcome {
+                text: join_results(
+                    sequence.iter().map(|i| items[*i].text.clone()).collect(),
+                    separator,
+                    conjunction,
+                ),
+                probability: probability * chance,
+            }));
+        }
+
+        if length == max_count {
+            break;
+        }
+
+        let mut next = vec![];
+
+        for (sequence, probability) in sequences {
+            let used: f64 = match unique {
+                true => sequence.iter().map(|i| items[*i].probability).sum(),
+                false => 0.0,
+            };
+
+            for (i, item) in items.iter().enumerate() {
+                if !(unique && sequence.contains(&i)) && used < 1.0 {
+                    let mut sequence = sequence.clone();
+                    sequence.push(i);
+                    next.push((sequence, probability * item.probability / (1.0 - used)));
+                }
+            }
+        }
+
+        sequences = next;
+    }
+
+    for outcome in outcomes.iter_mut() {
+        for filter in stages.joined {
+            filter.apply(&mut outcome.text, custom, ctx).ok()?;
+        }
+    }
+
+    Some(merge_outcomes(outcomes))
+}
+
+/**
+ * Estimates the number of outputs of an interpolation of something with
+ * `size` outputs, given its `unique(N)` or `repeat(N)` filter.
+ */
+fn count_size(size: f64, filters: &[FilterOp]) -> f64 {
+    let Some((expr, _)) = count_filter(filters) else {
+        return size;
+    };
+
+    match expr.bounds() {
+        Some((lo, hi)) => (lo.max(0)..=hi.max(0))
+            .map(|n| size.powi(n.min(i32::MAX as i64) as i32))
+            .sum(),
+        None => f64::INFINITY,
+    }
+}
+
+fn merge_outcomes(outcomes: Vec<Outcome>) -> Vec<Outcome> {
+    let mut merged: Vec<Outcome> = vec![];
+    let mut positions: HashMap<String, usize> = HashMap::new();
+
+    for outcome in outcomes {
+        match positions.get(&outcome.text) {
+            Some(&i) => merged[i].probability += outcome.probability,
+            None => {
+                positions.insert(outcome.text.clone(), merged.len());
+                merged.push(outcome);
+            }
+        }
+    }
+
+    // stable, so equally likely outcomes stay in order
+    merged.sort_by(|a, b| b.probability.total_cmp(&a.probability));
+    merged
+}
+
+/**
+ * The exact distribution of a dice expression's value, in ascending order.
+ * `None` when it can't be computed in reasonable time, or has no upper bound
+ * (exploding dice).
+ */
+pub fn expr_distribution(expr: &DiceExpr) -> Option<Vec<(i64, f64)>> {
+    match expr {
+        DiceExpr::Number(n) => Some(vec![(*n, 1.0)]),
+        DiceExpr::Dice(dice) if dice.explode => None,
+        DiceExpr::Dice(dice) if dice.keep.is_some() => kept_dice_distribution(dice),
+        DiceExpr::Dice(dice) => Some(
+            dice_distribution(dice.count, dice.sides)?
+                .into_iter()
+                .map(|(sum, p)| (sum as i64, p))
+                .collect(),
+        ),
+        DiceExpr::BinaryOp(op, lhs, rhs) => {
+            let lhs = expr_distribution(lhs)?;
+            let rhs = expr_distribution(rhs)?;
+
+            if lhs.len() * rhs.len() > OUTCOME_LIMIT * 10 {
+                return None;
+            }
+
+            let mut values: BTreeMap<i64, f64> = BTreeMap::new();
+
+            for (a, p) in lhs.iter() {
+                for (b, q) in rhs.iter() {
+                    *values.entry(op.apply(*a, *b)?).or_default() += p * q;
+                }
+            }
+
+            Some(values.into_iter().collect())
+        }
+    }
+}
+
+/**
+ * Keeping or dropping dice depends on every individual face, so this goes
+ * through every possible roll.
+ */
+fn kept_dice_distribution(dice: &Dice) -> Option<Vec<(i64, f64)>> {
+    let rolls = (dice.sides as f64).powi(dice.count as i32);
+
+    if rolls > (OUTCOME_LIMIT * 10) as f64 {
+        return None;
+    }
+
+    let rolls = rolls as usize;
+    let mut values: BTreeMap<i64, f64> = BTreeMap::new();
+    let mut faces = vec![1; dice.count];
+
+    for _ in 0..rolls {
+        let total = faces
+            .iter()
+            .zip(dice.kept(&faces))
+            .filter(|(_, kept)| *kept)
+            .map(|(face, _)| *face as i64)
+            .sum();
+
+        *values.entry(total).or_default() += 1.0 / rolls as f64;
+
+        // advance to the next roll, like counting in base `sides`
+        for face in faces.iter_mut() {
+            if *face < dice.sides {
+                *face += 1;
+                break;
+            }
+
+            *face = 1;
+        }
+    }
+
+    Some(values.into_iter().collect())
+}
+
+/**
+ * An upper bound on the number of values a dice expression can produce.
+ */
+fn expr_size(expr: &DiceExpr) -> f64 {
+    match expr {
+        DiceExpr::Number(_) => 1.0,
+        DiceExpr::Dice(dice) if dice.explode => f64::INFINITY,
+        DiceExpr::Dice(dice) => {
+            let kept = dice
+                .kept(&vec![1; dice.count])
+                .iter()
+                .filter(|k| **k)
+                .count();
+
+            kept as f64 * dice.sides.saturating_sub(1) as f64 + 1.0
+        }
+        DiceExpr::BinaryOp(_, lhs, rhs) => expr_size(lhs) * expr_size(rhs),
+    }
+}
+
+/**
+ * The exact distribution of the sum of `count` dice with `sides` sides, as
+ * `(sum, probability)` pairs in ascending order of the sum.
+ */
+pub fn dice_distribution(count: usize, sides: usize) -> Option<Vec<(usize, f64)>> {
+    if count == 0 || sides == 0 {
+        return Some(vec![(0, 1.0)]);
+    }
+
+    if count.saturating_mul(sides - 1).saturating_add(1) > OUTCOME_LIMIT {
+        return None;
+    }
+
+    // probabilities[i] is the chance of the dice rolled so far summing to
+    // `i + dice rolled`
+    let mut probabilities = vec![1.0];
+
+    for _ in 0..count {
+        let mut next = vec![0.0; probabilities.len() + sides - 1];
+        let mut window = 0.0;
+
+        // each entry sums the previous `sides` entries, computed as a
+        // sliding window
+        for (i, p) in next.iter_mut().enumerate() {
+            if i < probabilities.len() {
+                window += probabilities[i];
+            }
+
+            if i >= sides {
+                window -= probabilities[i - sides];
+            }
+
+            *p = window / sides as f64;
+        }
+
+        probabilities = next;
+    }
+
+    Some(
+        probabilities
+            .into_iter()
+            .enumerate()
+            .map(|(i, p)| (i + count, p))
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::table_collection::TableCollection;
+
+    fn assert_close(a: f64, b: f64) {
+        assert!((a - b).abs() < 1e-9, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn test_dice_distribution() {
+        let distribution = dice_distribution(2, 6).unwrap();
+
+        assert_eq!(distribution.len(), 11);
+        assert_eq!(distribution[0].0, 2);
+        assert_close(distribution[0].1, 1.0 / 36.0);
+        assert_eq!(distribution[5].0, 7);
+        assert_close(distribution[5].1, 6.0 / 36.0);
+        assert_close(distribution.iter().map(|(_, p)| p).sum(), 1.0);
+
+        assert_eq!(dice_distribution(1, 20).unwrap().len(), 20);
+        assert!(dice_distribution(1000, 1000).is_none());
+    }
+
+    #[test]
+    fn test_expr_distribution() {
+        let parse = |expr: &str| match &TableCollection::parse(&format!(
+            "---\nid: roll\ntitle: Roll\n---\n1: {{{}}}",
+            expr
+        ))
+        .unwrap()
+        .table("roll")
+        .unwrap()
+        .rules[0]
+            .parts[0]
+        {
+            RuleInst::DiceRoll(expr, _) => expr_distribution(expr),
+            part => panic!("Unexpected part: {:?}", part),
+        };
+
+        let plus_three = parse("2d6+3").unwrap();
+        assert_eq!(plus_three.first().unwrap().0, 5);
+        assert_eq!(plus_three.last().unwrap().0, 15);
+
+        let times_ten = parse("1d4*10").unwrap();
+        assert_eq!(
+            times_ten.iter().map(|(v, _)| *v).collect::<Vec<i64>>(),
+            vec![10, 20, 30, 40]
+        );
+
+        // 3 with advantage: the higher of two d20s is 3 in 5 of 400 rolls
+        let advantage = parse("2d20kh1").unwrap();
+        assert_eq!(advantage.len(), 20);
+        assert_close(advantage[2].1, 5.0 / 400.0);
+
+        let mixed = parse("1d8+1d6-2").unwrap();
+        assert_eq!(mixed.first().unwrap().0, 0);
+        assert_eq!(mixed.last().unwrap().0, 12);
+        assert_close(mixed.iter().map(|(_, p)| p).sum(), 1.0);
+
+        assert_eq!(parse("3d6!"), None);
+    }
+
+    #[test]
+    fn test_analyze_table() {
+        let collection = TableCollection::parse(
+            "---
+id: color
+title: Colors
+---
+3: Red
+1: Blue {1d2} or {1d2}
+4: {shade} Green
+
+---
+id: shade
+title: Shades
+---
+1: light",
+        )
+        .unwrap();
+
+        let analysis = collection.analyze("color").unwrap();
+
+        assert_eq!(analysis.rules.len(), 3);
+        assert_close(analysis.rules[0].probability, 0.375);
+        assert_close(analysis.rules[1].probability, 0.125);
+        assert_close(analysis.rules[2].probability, 0.5);
+
+        assert_eq!(
+            analysis.rules[0].outcomes,
+            Some(vec![Outcome {
+                text: "Red".to_string(),
+                probability: 1.0
+            }])
+        );
+
+        let outcomes = analysis.rules[1].outcomes.as_ref().unwrap();
+        let texts: Vec<&str> = outcomes.iter().map(|o| o.text.as_str()).collect();
+
+        assert_eq!(
+            texts,
+            vec!["Blue 1 or 1", "Blue 1 or 2", "Blue 2 or 1", "Blue 2 or 2"]
+        );
+        assert_close(outcomes[0].probability, 0.25);
+
+        assert_eq!(analysis.rules[2].outcomes, None);
+    }
+
+    #[test]
+    fn test_enumerate_table() {
+        let collection = TableCollection::parse(
+            "---
+id: color
+title: Colors
+---
+1: {shade|unique(2)|join(', ', ' and ')} Red
+1: {shade|capitalize} Blue {1d2}
+
+---
+id: shade
+title: Shades
+---
+3: light
+1: dark",
+        )
+        .unwrap();
+
+        let enumeration = collection.enumerate("color").unwrap();
+        assert_eq!(enumeration.size_estimate, 8.0);
+
+        let outcomes = enumeration.outcomes.unwrap();
+        let find = |text: &str| {
+            outcomes
+                .iter()
+                .find(|o| o.text == text)
+                .unwrap()
+                .probability
+        };
+
+        assert_eq!(outcomes.len(), 6);
+        assert_close(outcomes.iter().map(|o| o.probability).sum(), 1.0);
+        assert_close(find("light and dark Red"), 0.5 * 0.75);
+        assert_close(find("dark and light Red"), 0.5 * 0.25);
+        assert_close(find("Light Blue 1"), 0.5 * 0.75 * 0.5);
+        assert_close(find("Dark Blue 2"), 0.5 * 0.25 * 0.5);
+    }
+
+    #[test]
+    fn test_enumerate_recursive_table() {
+        let collection = TableCollection::parse(
+            "---
+id: chain
+title: Chain
+---
+1: link
+1: link-{chain}",
+        )
+        .unwrap();
+
+        let enumeration = collection.enumerate("chain").unwrap();
+
+        assert_eq!(enumeration.size_estimate, f64::INFINITY);
+        assert_eq!(enumeration.outcomes, None);
+    }
+}
@@ -0,0 +1,60 @@
+use rand::distributions::WeightedIndex;
+use rand::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+
+use crate::table_collection::TableDefinition;
+
+/**
+ * How many picks are remembered for each table, so the longest cooldown.
+ */
+pub static RECENT_LIMIT: usize = 100;
+
+/**
+ * The rules recently picked by weight from each table, as indices of its
+ * rules by table id, oldest first. Serializable so they can be saved and
+ * restored with a session.
+ */
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RecentPicks {
+    pub picked: BTreeMap<String, VecDeque<usize>>,
+}
+
+impl RecentPicks {
+    /**
+     * Picks a rule from the table by weight, leaving out the rules picked in
+     * its last `cooldown` picks. When that would leave nothing to pick, the
+     * oldest of those are allowed again.
+     */
+    pub fn pick(&mut self, table: &TableDefinition, cooldown: usize, rng: &mut impl Rng) -> usize {
+        let picked = self.picked.entry(table.id.clone()).or_default();
+
+        let index = if cooldown == 0 || picked.is_empty() {
+            table.distribution.sample(rng)
+        } else {
+            let mut weights = table.weights.clone();
+
+            for i in picked.iter().rev().take(cooldown) {
+                let weight = std::mem::replace(&mut weights[*i], 0.0);
+
+                if weights.iter().all(|w| *w <= 0.0) {
+                    weights[*i] = weight;
+                    break;
+                }
+            }
+
+            match WeightedIndex::new(&weights) {
+                Ok(distribution) => distribution.sample(rng),
+                Err(_) => table.distribution.sample(rng),
+            }
+        };
+
+        if picked.len() == RECENT_LIMIT {
+            picked.pop_front();
+        }
+
+        picked.push_back(index);
+
+        index
+    }
+}
@@ -0,0 +1,147 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::rc::Rc;
+use wasm_bindgen::{JsCast, JsValue};
+use web_sys::js_sys::{Array, Function, Object};
+
+use crate::table_collection::{FilterOp, TableError};
+
+// names the parser treats as something other than a transform filter
+pub static RESERVED_NAMES: &[&str] = &["unique", "repeat", "join", "count"];
+
+thread_local! {
+    // the names of the filters registered for the definitions being parsed,
+    // see `parse_with`
+    static PARSE_FILTERS: RefCell<HashSet<String>> = RefCell::new(HashSet::new());
+}
+
+/**
+ * A filter defined outside the engine, e.g. `{price|gold}` converting copper
+ * to gold. Closures taking and returning a string implement it.
+ */
+pub trait Filter {
+    fn apply(&self, value: &str) -> Result<String, TableError>;
+}
+
+impl<F: Fn(&str) -> String> Filter for F {
+    fn apply(&self, value: &str) -> Result<String, TableError> {
+        Ok(self(value))
+    }
+}
+
+/**
+ * A filter calling a JS function that takes and returns a string.
+ */
+pub struct JsFilter(pub Function);
+
+impl Filter for JsFilter {
+    fn apply(&self, value: &str) -> Result<String, TableError> {
+        let result = self
+            .0
+            .call1(&JsValue::NULL, &JsValue::from_str(value))
+            .map_err(|e| TableError::CallError(format!("Filter failed: {:?}", e)))?;
+
+        result.as_string().ok_or(TableError::CallError(format!(
+            "Filter returned {:?} instead of a string",
+            result
+        )))
+    }
+}
+
+/**
+ * The filters available to a collection's rules by name. They're given when
+ * parsing, so a rule using any other name is a syntax error.
+ */
+#[derive(Clone, Default)]
+pub struct CustomFilters(HashMap<String, Rc<dyn Filter>>);
+
+impl CustomFilters {
+    /**
+     * Makes `filter` available as `{table|name}`, replacing any filter
+     * already registered as `name`.
+     */
+    pub fn register(
+        &mut self,
+        name: &str,
+        filter: impl Filter + 'static,
+    ) -> Result<(), TableError> {
+        let mut chars = name.chars();
+
+        if !chars.next().is_some_and(|c| c.is_ascii_alphabetic())
+            || !chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        {
+            return Err(TableError::CallError(format!(
+                "Invalid filter name `{}`, expected a letter followed by letters, digits or `_`",
+                name
+            )));
+        }
+
+        let is_builtin = matches!(name.parse(), Ok(f) if !matches!(f, FilterOp::Custom(_)));
+
+        if is_builtin || RESERVED_NAMES.contains(&name) {
+            return Err(TableError::CallError(format!(
+                "`{}` is a built-in filter",
+                name
+            )));
+        }
+
+        self.0.insert(name.to_string(), Rc::new(filter));
+
+        Ok(())
+    }
+
+    /**
+     * Filters from a JS object mapping names to functions, e.g.
+     * `{ gold: (copper) => ... }`.
+     */
+    pub fn from_js(filters: &Object) -> Result<Self, TableError> {
+        let mut custom = Self::default();
+
+        // `[name, callback]` pairs
+        for entry in Object::entries(filters).iter() {
+            let entry: Array = entry.unchecked_into();
+            let name = entry.get(0).as_string().unwrap_or_default();
+            let callback = entry.get(1).dyn_into::<Function>().map_err(|_| {
+                TableError::CallError(format!("Filter `{}` must be a function", name))
+            })?;
+
+            custom.register(&name, JsFilter(callback))?;
+        }
+
+        Ok(custom)
+    }
+
+    pub fn apply(&self, name: &str, value: &str) -> Result<String, TableError> {
+        match self.0.get(name) {
+            Some(filter) => filter.apply(value),
+            None => Err(TableError::CallError(format!("Unknown filter `{}`", name))),
+        }
+    }
+}
+
+/**
+ * Runs `parse` with the names in `filters` accepted as filters by the parser.
+ */
+pub(crate) fn parse_with<T>(filters: &CustomFilters, parse: impl FnOnce() -> T) -> T {
+    PARSE_FILTERS.with(|names| *names.borrow_mut() = filters.0.keys().cloned().collect());
+
+    let result = parse();
+
+    PARSE_FILTERS.with(|names| names.borrow_mut().clear());
+
+    result
+}
+
+/**
+ * Whether `name` is registered for the definitions being parsed.
+ */
+pub(crate) fn is_parse_filter(name: &str) -> bool {
+    PARSE_FILTERS.with(|names| names.borrow().contains(name))
+}
+
+impl fmt::Debug for CustomFilters {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.0.keys()).finish()
+    }
+}
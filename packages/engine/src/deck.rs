@@ -0,0 +1,59 @@
+use rand::distributions::WeightedIndex;
+use rand::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::table_collection::TableDefinition;
+
+/**
+ * The cards drawn from each `mode: deck` table since it was last shuffled, as
+ * the indices of its rules by table id. Serializable so decks can be saved
+ * and restored with a session.
+ */
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct DeckState {
+    pub drawn: BTreeMap<String, Vec<usize>>,
+}
+
+impl DeckState {
+    /**
+     * Draws a card (the index of a rule) from the table's deck by weight,
+     * reshuffling first when every card has been drawn.
+     */
+    pub fn draw(&mut self, table: &TableDefinition, rng: &mut impl Rng) -> usize {
+        let drawn = self.drawn.entry(table.id.clone()).or_default();
+
+        let weights: Vec<f32> = table
+            .weights
+            .iter()
+            .enumerate()
+            .map(|(i, weight)| if drawn.contains(&i) { 0.0 } else { *weight })
+            .collect();
+
+        let distribution = WeightedIndex::new(&weights).unwrap_or_else(|_| {
+            drawn.clear();
+            table.distribution.clone()
+        });
+
+        let index = distribution.sample(rng);
+        drawn.push(index);
+
+        index
+    }
+
+    /**
+     * The indices of the rules still in the table's deck.
+     */
+    pub fn remaining(&self, table: &TableDefinition) -> Vec<usize> {
+        let drawn = self.drawn.get(&table.id);
+
+        (0..table.rules.len())
+            .filter(|i| table.weights[*i] > 0.0)
+            .filter(|i| !drawn.is_some_and(|drawn| drawn.contains(i)))
+            .collect()
+    }
+
+    pub fn reshuffle(&mut self, id: &str) {
+        self.drawn.remove(id);
+    }
+}
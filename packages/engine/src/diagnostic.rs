@@ -0,0 +1,316 @@
+use nom_supreme::error::{BaseErrorKind, ErrorTree, StackContext};
+use serde::Serialize;
+use std::fmt;
+
+use crate::nom_parser::Span;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/**
+ * A problem found in a table definition source, located precisely enough
+ * for an editor to underline the offending text. Lines and columns are
+ * 1-indexed, offsets are byte offsets into the source.
+ */
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub offset: usize,
+    pub line: u32,
+    pub column: u32,
+    pub end_offset: usize,
+    pub end_line: u32,
+    pub end_column: u32,
+    // innermost context first
+    pub context: Vec<String>,
+}
+
+impl Diagnostic {
+    /**
+     * Builds a diagnostic for the span `offset..end_offset` of `source`,
+     * computing line/column positions for both ends.
+     */
+    pub fn new(
+        severity: Severity,
+        message: String,
+        source: &str,
+        offset: usize,
+        end_offset: usize,
+        context: Vec<String>,
+    ) -> Self {
+        let (line, column) = line_column(source, offset);
+        let (end_line, end_column) = line_column(source, end_offset);
+
+        Self {
+            severity,
+            message,
+            offset,
+            line,
+            column,
+            end_offset,
+            end_line,
+            end_column,
+            context,
+        }
+    }
+
+    /**
+     * Builds a diagnostic covering the single token at `offset`.
+     */
+    pub(crate) fn at_token(
+        severity: Severity,
+        message: String,
+        source: &str,
+        offset: usize,
+    ) -> Self {
+        Self::new(
+            severity,
+            message,
+            source,
+            offset,
+            token_end(source, offset),
+            vec![],
+        )
+    }
+
+    /**
+     * Converts a parser error into a diagnostic. When several alternatives
+     * failed, the one that got furthest into the input is reported since it
+     * is almost always the one the author intended.
+     */
+    pub(crate) fn from_error_tree(source: &str, error: &ErrorTree<Span>) -> Self {
+        let mut failures = vec![];
+        collect_failures(error, &mut vec![], &mut failures);
+
+        let failure = failures
+            .into_iter()
+            .rev()
+            .max_by_key(|failure| failure.location)
+            .unwrap_or(Failure {
+                location: 0,
+                message: "Invalid table definition".to_string(),
+                is_external: true,
+                context: vec![],
+            });
+
+        // errors raised by our own validation are more specific than the
+        // generic context messages attached by the surrounding parsers
+        let message = if failure.is_external {
+            failure.message
+        } else {
+            failure.context.first().cloned().unwrap_or(failure.message)
+        };
+        let location = failure.location;
+
+        Self::new(
+            Severity::Error,
+            message,
+            source,
+            location,
+            token_end(source, location),
+            failure.context,
+        )
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+pub fn format_diagnostics(diagnostics: &[Diagnostic]) -> String {
+    diagnostics
+        .iter()
+        .map(|d| d.to_string())
+        .collect::<Vec<String>>()
+        .join("; ")
+}
+
+struct Failure {
+    location: usize,
+    message: String,
+    is_external: bool,
+    context: Vec<String>,
+}
+
+/**
+ * Walks an `ErrorTree` collecting every base failure along with its location
+ * and the chain of `.context(..)` messages wrapping it (innermost first).
+ */
+fn collect_failures(
+    error: &ErrorTree<Span>,
+    outer_contexts: &mut Vec<String>,
+    failures: &mut Vec<Failure>,
+) {
+    match error {
+        ErrorTree::Base { location, kind } => {
+            let mut context = outer_contexts.clone();
+            context.reverse();
+
+            failures.push(Failure {
+                location: location.location_offset(),
+                message: describe(kind),
+                is_external: matches!(kind, BaseErrorKind::External(_)),
+                context,
+            });
+        }
+        ErrorTree::Stack { base, contexts } => {
+            let depth = outer_contexts.len();
+
+            // contexts are stored innermost first, push them outermost first
+            for (_, ctx) in contexts.iter().rev() {
+                if let StackContext::Context(ctx) = ctx {
+                    outer_contexts.push(ctx.to_string());
+                }
+            }
+
+            collect_failures(base, outer_contexts, failures);
+            outer_contexts.truncate(depth);
+        }
+        ErrorTree::Alt(siblings) => {
+            for sibling in siblings {
+                collect_failures(sibling, outer_contexts, failures);
+            }
+        }
+    }
+}
+
+fn describe(
+    kind: &BaseErrorKind<&'static str, Box<dyn std::error::Error + Send + Sync + 'static>>,
+) -> String {
+    match kind {
+        BaseErrorKind::Expected(expectation) => format!("expected {}", expectation),
+        BaseErrorKind::Kind(kind) => format!("unexpected input ({})", kind.description()),
+        BaseErrorKind::External(e) => e.to_string(),
+    }
+}
+
+/**
+ * Finds the end of the token starting at `offset`, stopping at whitespace so
+ * that the span covers a single "word" of the source. The span is empty when
+ * the error is reported at whitespace or at the end of the input.
+ */
+fn token_end(source: &str, offset: usize) -> usize {
+    let rest = &source[offset..];
+
+    match rest.find(char::is_whitespace) {
+        Some(0) => offset,
+        Some(n) => offset + n,
+        None => source.len(),
+    }
+}
+
+fn line_column(source: &str, offset: usize) -> (u32, u32) {
+    let before = &source[..offset];
+    let line = before.matches('\n').count() as u32 + 1;
+    let line_start = before.rfind('\n').map(|i| i + 1).unwrap_or(0);
+
+    (line, before[line_start..].chars().count() as u32 + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::table_collection::{GenContext, TableCollection, TableError};
+
+    #[test]
+    fn test_diagnostic_points_at_invalid_rule_weight() {
+        let source = "---
+id: color
+title: Colors
+---
+1: Red
+x: Blue";
+
+        let diagnostics = TableCollection::diagnostics_for(source);
+
+        assert_eq!(diagnostics.len(), 1);
+
+        let diagnostic = &diagnostics[0];
+        assert_eq!(diagnostic.severity, Severity::Error);
+        assert_eq!(diagnostic.line, 6);
+        assert_eq!(diagnostic.column, 1);
+        assert_eq!(&source[diagnostic.offset..diagnostic.end_offset], "x:");
+        assert_eq!(
+            diagnostic.message,
+            "Invalid rule weight, expected an integer or float"
+        );
+        assert!(diagnostic
+            .context
+            .contains(&"Invalid table definition".to_string()));
+    }
+
+    #[test]
+    fn test_diagnostic_for_invalid_rule_text() {
+        let source = "---
+id: color
+title: Colors
+---
+1: literal {table|}";
+
+        let result = TableCollection::parse(source);
+
+        if let Err(TableError::SyntaxError(diagnostics)) = result {
+            assert_eq!(diagnostics[0].line, 5);
+            assert_eq!(diagnostics[0].column, 12);
+        } else {
+            panic!("Unexpected result: {:?}", result);
+        }
+    }
+
+    #[test]
+    fn test_diagnostics_for_every_broken_table() {
+        let source = "---
+id: color
+title: Colors
+---
+1: Red
+x: Blue
+
+---
+id: shape
+title: Shapes
+---
+1: Circle
+
+---
+id: size
+title: Sizes
+---
+1: {tiny|}";
+
+        let collection = TableCollection::parse_recovering(source);
+        let lines: Vec<u32> = collection.diagnostics().iter().map(|d| d.line).collect();
+
+        assert_eq!(lines, vec![6, 18]);
+        assert!(collection
+            .gen_with("shape", &mut GenContext::new(None))
+            .is_ok());
+    }
+
+    #[test]
+    fn test_no_diagnostics_for_valid_source() {
+        let diagnostics = TableCollection::diagnostics_for(
+            "---
+id: color
+title: Colors
+---
+1: Red",
+        );
+
+        assert!(diagnostics.is_empty());
+    }
+
+    #[test]
+    fn test_line_column() {
+        assert_eq!(line_column("abc\ndef", 0), (1, 1));
+        assert_eq!(line_column("abc\ndef", 5), (2, 2));
+        assert_eq!(line_column("abc\ndef", 7), (2, 4));
+    }
+}
@@ -0,0 +1,426 @@
+use rand::Rng;
+use serde::Serialize;
+use std::fmt;
+
+use crate::table_collection::TableError;
+
+/**
+ * How many extra dice a single exploding die may add, so that e.g. `{3d1!}`
+ * terminates.
+ */
+static EXPLODE_LIMIT: usize = 100;
+
+/**
+ * How many dice can be rolled at once, e.g. in `{1000d6}`.
+ */
+pub static DICE_LIMIT: usize = 1000;
+
+/**
+ * A dice expression such as `2d6+3`, `4d6kh3` or `1d8+1d6-2`.
+ */
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub enum DiceExpr {
+    Number(i64),
+    Dice(Dice),
+    // (operator, left, right)
+    BinaryOp(Operator, Box<DiceExpr>, Box<DiceExpr>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub enum Operator {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Dice {
+    pub count: usize,
+    pub sides: usize,
+    // roll another die whenever a die rolls its highest face
+    pub explode: bool,
+    pub keep: Option<Keep>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub enum Keep {
+    Highest(usize),
+    Lowest(usize),
+    DropHighest(usize),
+    DropLowest(usize),
+}
+
+/**
+ * The dice rolled for one `NdM` term of an expression.
+ */
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RolledDice {
+    pub notation: String,
+    // every die rolled in order, including extra dice from explosions
+    pub faces: Vec<Face>,
+    pub total: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Face {
+    pub value: usize,
+    // whether the die counts towards the total, see `Keep`
+    pub kept: bool,
+}
+
+impl DiceExpr {
+    /**
+     * Rolls the expression, appending every set of dice rolled to `rolls`.
+     */
+    pub fn roll(&self, rng: &mut impl Rng, rolls: &mut Vec<RolledDice>) -> Result<i64, TableError> {
+        match self {
+            DiceExpr::Number(n) => Ok(*n),
+            DiceExpr::Dice(dice) => {
+                let rolled = dice.roll(rng);
+                let total = rolled.total;
+
+                rolls.push(rolled);
+                Ok(total)
+            }
+            DiceExpr::BinaryOp(op, lhs, rhs) => {
+                let lhs = lhs.roll(rng, rolls)?;
+                let rhs = rhs.roll(rng, rolls)?;
+
+                op.apply(lhs, rhs).ok_or(TableError::CallError(format!(
+                    "Division by zero in dice expression {}",
+                    self
+                )))
+            }
+        }
+    }
+
+    /**
+     * The lowest and highest possible values, `None` for exploding dice (or
+     * division by a range including zero).
+     */
+    pub fn bounds(&self) -> Option<(i64, i64)> {
+        match self {
+            DiceExpr::Number(n) => Some((*n, *n)),
+            DiceExpr::Dice(dice) if dice.explode => None,
+            DiceExpr::Dice(dice) => {
+                let kept = dice
+                    .kept(&vec![1; dice.count])
+                    .iter()
+                    .filter(|k| **k)
+                    .count() as i64;
+
+                Some((kept, kept * dice.sides as i64))
+            }
+            DiceExpr::BinaryOp(op, lhs, rhs) => {
+                let (a, b) = lhs.bounds()?;
+                let (c, d) = rhs.bounds()?;
+
+                if *op == Operator::Divide && c <= 0 && d >= 0 {
+                    return None;
+                }
+
+                // the extremes of `+ - * /` over two ranges are always at the
+                // corners
+                let corners = [
+                    op.apply(a, c)?,
+                    op.apply(a, d)?,
+                    op.apply(b, c)?,
+                    op.apply(b, d)?,
+                ];
+
+                Some((*corners.iter().min()?, *corners.iter().max()?))
+            }
+        }
+    }
+
+    /**
+     * Whether the expression rolls any dice, `{3}` alone isn't a dice roll.
+     */
+    pub fn has_dice(&self) -> bool {
+        match self {
+            DiceExpr::Number(_) => false,
+            DiceExpr::Dice(_) => true,
+            DiceExpr::BinaryOp(_, lhs, rhs) => lhs.has_dice() || rhs.has_dice(),
+        }
+    }
+}
+
+impl Operator {
+    /**
+     * Applies the operator, returning `None` when dividing by zero. Division
+     * rounds towards zero.
+     */
+    pub fn apply(&self, lhs: i64, rhs: i64) -> Option<i64> {
+        match self {
+            Operator::Add => Some(lhs.saturating_add(rhs)),
+            Operator::Subtract => Some(lhs.saturating_sub(rhs)),
+            Operator::Multiply => Some(lhs.saturating_mul(rhs)),
+            Operator::Divide => lhs.checked_div(rhs),
+        }
+    }
+
+    fn precedence(&self) -> u8 {
+        match self {
+            Operator::Add | Operator::Subtract => 1,
+            Operator::Multiply | Operator::Divide => 2,
+        }
+    }
+}
+
+impl Dice {
+    pub fn roll(&self, rng: &mut impl Rng) -> RolledDice {
+        let mut values = vec![];
+
+        for _ in 0..self.count {
+            let mut value = roll_die(self.sides, rng);
+            values.push(value);
+
+            let mut explosions = 0;
+
+            while self.explode && value == self.sides && explosions < EXPLODE_LIMIT {
+                value = roll_die(self.sides, rng);
+                values.push(value);
+                explosions += 1;
+            }
+        }
+
+        let kept = self.kept(&values);
+        let total = values
+            .iter()
+            .zip(kept.iter())
+            .filter(|(_, kept)| **kept)
+            .map(|(value, _)| *value as i64)
+            .sum();
+
+        RolledDice {
+            notation: self.to_string(),
+            faces: values
+                .into_iter()
+                .zip(kept)
+                .map(|(value, kept)| Face { value, kept })
+                .collect(),
+            total,
+        }
+    }
+
+    /**
+     * Which of the rolled `values` count towards the total.
+     */
+    pub fn kept(&self, values: &[usize]) -> Vec<bool> {
+        let Some(keep) = self.keep else {
+            return vec![true; values.len()];
+        };
+
+        // indices of the values from lowest to highest, ties in roll order
+        let mut order: Vec<usize> = (0..values.len()).collect();
+        order.sort_by_key(|i| values[*i]);
+
+        let len = values.len();
+        let kept_range = match keep {
+            Keep::Highest(n) => len.saturating_sub(n)..len,
+            Keep::Lowest(n) => 0..n.min(len),
+            Keep::DropHighest(n) => 0..len.saturating_sub(n),
+            Keep::DropLowest(n) => n.min(len)..len,
+        };
+
+        let mut kept = vec![false; len];
+
+        for i in &order[kept_range] {
+            kept[*i] = true;
+        }
+
+        kept
+    }
+}
+
+fn roll_die(sides: usize, rng: &mut impl Rng) -> usize {
+    // sample as `u32` since `usize` consumes a different amount of randomness
+    // on 32-bit (wasm) and 64-bit targets. Parsed dice have at most
+    // `u32::MAX` sides
+    let sides = u32::try_from(sides).unwrap_or(u32::MAX);
+
+    rng.gen_range(1..=sides) as usize
+}
+
+impl fmt::Display for DiceExpr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DiceExpr::Number(n) => write!(f, "{}", n),
+            DiceExpr::Dice(dice) => write!(f, "{}", dice),
+            DiceExpr::BinaryOp(op, lhs, rhs) => {
+                // parenthesize anything that binds looser than `op`
+                let wrap = |expr: &DiceExpr| match expr {
+                    DiceExpr::BinaryOp(inner, ..) if inner.precedence() < op.precedence() => {
+                        format!("({})", expr)
+                    }
+                    _ => expr.to_string(),
+                };
+
+                let rhs = match rhs.as_ref() {
+                    // `a-(b+c)` and `a/(b*c)` need parentheses too
+                    DiceExpr::BinaryOp(inner, ..)
+                        if inner.precedence() == op.precedence()
+                            && matches!(op, Operator::Subtract | Operator::Divide) =>
+                    {
+                        format!("({})", rhs)
+                    }
+                    _ => wrap(rhs),
+                };
+
+                write!(f, "{}{}{}", wrap(lhs), op, rhs)
+            }
+        }
+    }
+}
+
+impl fmt::Display for Operator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let symbol = match self {
+            Operator::Add => "+",
+            Operator::Subtract => "-",
+            Operator::Multiply => "*",
+            Operator::Divide => "/",
+        };
+
+        write!(f, "{}", symbol)
+    }
+}
+
+impl fmt::Display for Dice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}d{}", self.count, self.sides)?;
+
+        if self.explode {
+            write!(f, "!")?;
+        }
+
+        match self.keep {
+            Some(Keep::Highest(n)) => write!(f, "kh{}", n),
+            Some(Keep::Lowest(n)) => write!(f, "kl{}", n),
+            Some(Keep::DropHighest(n)) => write!(f, "dh{}", n),
+            Some(Keep::DropLowest(n)) => write!(f, "dl{}", n),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dice(count: usize, sides: usize) -> Dice {
+        Dice {
+            count,
+            sides,
+            explode: false,
+            keep: None,
+        }
+    }
+
+    #[test]
+    fn test_roll_dice() {
+        let mut rng = rand::thread_rng();
+
+        for _ in 0..10000 {
+            let roll = dice(1, 6).roll(&mut rng).total;
+            assert!(roll >= 1);
+            assert!(roll <= 6);
+        }
+
+        for _ in 0..10000 {
+            let roll = dice(5, 10).roll(&mut rng).total;
+            assert!(roll >= 5);
+            assert!(roll <= 50);
+        }
+    }
+
+    #[test]
+    fn test_roll_most_sides() {
+        let mut rng = rand::thread_rng();
+        let value = dice(1, u32::MAX as usize).roll(&mut rng).total;
+
+        assert!((1..=u32::MAX as i64).contains(&value));
+    }
+
+    #[test]
+    fn test_keep() {
+        let mut four_d6 = dice(4, 6);
+
+        four_d6.keep = Some(Keep::Highest(3));
+        assert_eq!(four_d6.kept(&[3, 1, 6, 3]), vec![true, false, true, true]);
+
+        four_d6.keep = Some(Keep::Lowest(1));
+        assert_eq!(four_d6.kept(&[3, 1, 6, 3]), vec![false, true, false, false]);
+
+        four_d6.keep = Some(Keep::DropHighest(1));
+        assert_eq!(four_d6.kept(&[3, 1, 6, 3]), vec![true, true, false, true]);
+
+        four_d6.keep = Some(Keep::DropLowest(5));
+        assert_eq!(
+            four_d6.kept(&[3, 1, 6, 3]),
+            vec![false, false, false, false]
+        );
+    }
+
+    #[test]
+    fn test_exploding_dice() {
+        let mut rng = rand::thread_rng();
+        let mut exploding = dice(3, 1);
+        exploding.explode = true;
+
+        let rolled = exploding.roll(&mut rng);
+
+        assert_eq!(rolled.faces.len(), 3 * (EXPLODE_LIMIT + 1));
+        assert_eq!(rolled.total, 3 * (EXPLODE_LIMIT as i64 + 1));
+    }
+
+    #[test]
+    fn test_roll_expression() {
+        let mut rng = rand::thread_rng();
+        let mut rolls = vec![];
+
+        // 1d8+1d6-2
+        let expr = DiceExpr::BinaryOp(
+            Operator::Subtract,
+            Box::new(DiceExpr::BinaryOp(
+                Operator::Add,
+                Box::new(DiceExpr::Dice(dice(1, 8))),
+                Box::new(DiceExpr::Dice(dice(1, 6))),
+            )),
+            Box::new(DiceExpr::Number(2)),
+        );
+
+        let total = expr.roll(&mut rng, &mut rolls).unwrap();
+
+        assert_eq!(expr.to_string(), "1d8+1d6-2");
+        assert_eq!(rolls.len(), 2);
+        assert_eq!(total, rolls[0].total + rolls[1].total - 2);
+
+        let divide_by_zero = DiceExpr::BinaryOp(
+            Operator::Divide,
+            Box::new(DiceExpr::Dice(dice(1, 8))),
+            Box::new(DiceExpr::Number(0)),
+        );
+
+        assert!(divide_by_zero.roll(&mut rng, &mut rolls).is_err());
+    }
+
+    #[test]
+    fn test_bounds() {
+        let mut four_d6 = dice(4, 6);
+        four_d6.keep = Some(Keep::Highest(3));
+
+        assert_eq!(DiceExpr::Dice(dice(1, 100)).bounds(), Some((1, 100)));
+        assert_eq!(DiceExpr::Dice(four_d6).bounds(), Some((3, 18)));
+        assert_eq!(
+            DiceExpr::BinaryOp(
+                Operator::Subtract,
+                Box::new(DiceExpr::Number(2)),
+                Box::new(DiceExpr::Dice(dice(2, 6))),
+            )
+            .bounds(),
+            Some((-10, 0))
+        );
+    }
+}
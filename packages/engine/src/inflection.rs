@@ -0,0 +1,484 @@
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/**
+ * A table's own exceptions to the built-in English rules, from its `plurals`
+ * and `articles` attributes. Both are keyed by lowercase word.
+ */
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Inflections {
+    // singular to plural
+    pub plurals: BTreeMap<String, String>,
+    // word to `a` or `an`
+    pub articles: BTreeMap<String, String>,
+}
+
+// nouns with the same singular and plural form
+static UNCOUNTABLE: &[&str] = &[
+    "aircraft",
+    "armor",
+    "armour",
+    "bison",
+    "cattle",
+    "deer",
+    "equipment",
+    "fish",
+    "gold",
+    "information",
+    "mail",
+    "moose",
+    "money",
+    "news",
+    "offspring",
+    "rice",
+    "salmon",
+    "series",
+    "sheep",
+    "silver",
+    "species",
+    "swine",
+    "trout",
+    "undead",
+];
+
+// (singular, plural)
+static IRREGULAR: &[(&str, &str)] = &[
+    ("cactus", "cacti"),
+    ("cafe", "cafes"),
+    ("chief", "chiefs"),
+    ("child", "children"),
+    ("crisis", "crises"),
+    ("die", "dice"),
+    ("dwarf", "dwarves"),
+    ("echo", "echoes"),
+    ("foot", "feet"),
+    ("fungus", "fungi"),
+    ("goose", "geese"),
+    ("hero", "heroes"),
+    ("hoof", "hooves"),
+    ("human", "humans"),
+    ("louse", "lice"),
+    ("mouse", "mice"),
+    ("ox", "oxen"),
+    ("person", "people"),
+    ("potato", "potatoes"),
+    ("quiz", "quizzes"),
+    ("roof", "roofs"),
+    ("safe", "safes"),
+    ("scarf", "scarves"),
+    ("shaman", "shamans"),
+    ("talisman", "talismans"),
+    ("thesis", "theses"),
+    ("thief", "thieves"),
+    ("tomato", "tomatoes"),
+    ("tooth", "teeth"),
+    ("torpedo", "torpedoes"),
+    ("volcano", "volcanoes"),
+    ("wharf", "wharves"),
+];
+
+// nouns ending in `-lf` that change it to `-lves`, also at the end of a
+// compound (`werewolf`, `bookshelf`); others just add `s` (`gulfs`)
+static LF_VES: &[&str] = &["calf", "elf", "half", "self", "shelf", "wolf"];
+
+/**
+ * The plural of a noun phrase, e.g. `long sword` -> `long swords` or
+ * `potion of healing` -> `potions of healing`.
+ *
+ * Words are looked up in the table's own `overrides` first (its `plurals`
+ * attribute), then the built-in uncountable and irregular nouns, before
+ * falling back to suffix rules.
+ */
+pub fn pluralize(phrase: &str, overrides: &BTreeMap<String, String>) -> String {
+    inflect_head(phrase, |word| plural(word, overrides))
+}
+
+/**
+ * The singular of a noun phrase, the inverse of `pluralize`.
+ */
+pub fn singularize(phrase: &str, overrides: &BTreeMap<String, String>) -> String {
+    inflect_head(phrase, |word| singular(word, overrides))
+}
+
+/**
+ * Inflects the head noun of the phrase: the last word, or the last word
+ * before an `of` (`bags of holding`). The case of the word is kept.
+ */
+fn inflect_head(phrase: &str, inflect: impl Fn(&str) -> String) -> String {
+    let head_end = phrase.find(" of ").unwrap_or(phrase.len());
+    let (head, rest) = phrase.split_at(head_end);
+
+    let word_end = head.trim_end_matches(|c: char| !c.is_alphabetic()).len();
+    let word_start = head[..word_end]
+        .rfind(|c: char| !c.is_alphabetic() && c != '\'' && c != '-')
+        .map(|i| i + 1)
+        .unwrap_or(0);
+
+    let word = &head[word_start..word_end];
+
+    if word.is_empty() {
+        return phrase.to_string();
+    }
+
+    let inflected = match_case(word, &inflect(&word.to_lowercase()));
+
+    format!(
+        "{}{}{}{}",
+        &head[..word_start],
+        inflected,
+        &head[word_end..],
+        rest
+    )
+}
+
+/**
+ * Applies the case of `original` to its inflected form: the shared prefix
+ * keeps its case, so `Elf` -> `Elves` and `NPC` -> `NPCs`, and other words
+ * in capitals stay in capitals.
+ */
+fn match_case(original: &str, word: &str) -> String {
+    let shared = original
+        .chars()
+        .zip(word.chars())
+        .take_while(|(a, b)| a.to_lowercase().eq(b.to_lowercase()))
+        .count();
+
+    let all_caps = original.chars().count() > 1 && original.chars().all(|c| !c.is_lowercase());
+
+    if all_caps && shared < original.chars().count() {
+        return word.to_uppercase();
+    }
+
+    original
+        .chars()
+        .take(shared)
+        .chain(word.chars().skip(shared))
+        .collect()
+}
+
+fn plural(word: &str, overrides: &BTreeMap<String, String>) -> String {
+    if let Some(plural) = overrides.get(word) {
+        return plural.clone();
+    }
+
+    // already plural
+    if UNCOUNTABLE.contains(&word) || is_irregular_plural(word, overrides) {
+        return word.to_string();
+    }
+
+    if let Some((_, plural)) = IRREGULAR.iter().find(|(singular, _)| *singular == word) {
+        return plural.to_string();
+    }
+
+    let stem = |suffix: &str| &word[..word.len() - suffix.len()];
+
+    if word.ends_with("man") {
+        format!("{}men", stem("man"))
+    } else if word.ends_with("fe") {
+        format!("{}ves", stem("fe"))
+    } else if LF_VES.iter().any(|w| word.ends_with(w))
+        || word.ends_with("eaf")
+        || word.ends_with("oaf")
+    {
+        format!("{}ves", stem("f"))
+    } else if word.ends_with('y') && !ends_with_vowel(stem("y")) {
+        format!("{}ies", stem("y"))
+    } else if word.ends_with("is") {
+        format!("{}es", stem("is"))
+    } else if ["s", "x", "z", "ch", "sh"]
+        .iter()
+        .any(|s| word.ends_with(s))
+    {
+        format!("{}es", word)
+    } else {
+        format!("{}s", word)
+    }
+}
+
+fn singular(word: &str, overrides: &BTreeMap<String, String>) -> String {
+    if let Some((singular, _)) = overrides.iter().find(|(_, plural)| *plural == word) {
+        return singular.clone();
+    }
+
+    // already singular, e.g. `cyclops` shouldn't lose its `s`
+    if UNCOUNTABLE.contains(&word)
+        || overrides.contains_key(word)
+        || IRREGULAR.iter().any(|(singular, _)| *singular == word)
+    {
+        return word.to_string();
+    }
+
+    // including the `-o` nouns taking `-es`, so any other `-oes` is `-oe` + `s`
+    if let Some((singular, _)) = IRREGULAR.iter().find(|(_, plural)| *plural == word) {
+        return singular.to_string();
+    }
+
+    let stem = |suffix: &str| &word[..word.len() - suffix.len()];
+
+    if word.ends_with("men") {
+        format!("{}man", stem("men"))
+    } else if word.ends_with("ives") {
+        format!("{}ife", stem("ives"))
+    } else if LF_VES
+        .iter()
+        .any(|w| word.ends_with(&format!("{}ves", &w[..w.len() - 1])))
+        || word.ends_with("eaves")
+        || word.ends_with("oaves")
+    {
+        format!("{}f", stem("ves"))
+    } else if word.ends_with("ies") && word.len() > 4 {
+        format!("{}y", stem("ies"))
+    } else if ["sses", "xes", "zes", "ches", "shes"]
+        .iter()
+        .any(|s| word.ends_with(s))
+    {
+        stem("es").to_string()
+    } else if word.ends_with('s') && !["ss", "us", "is"].iter().any(|s| word.ends_with(s)) {
+        stem("s").to_string()
+    } else {
+        word.to_string()
+    }
+}
+
+/**
+ * `a` or `an` for a phrase, by how its first word sounds rather than how
+ * it's spelled: `an hour`, `a unicorn`, `an 8-foot pole`, `an NPC`.
+ */
+pub fn indefinite_article(phrase: &str, overrides: &BTreeMap<String, String>) -> &'static str {
+    let word = phrase
+        .trim_start_matches(|c: char| !c.is_alphanumeric())
+        .split(|c: char| c.is_whitespace())
+        .next()
+        .unwrap_or_default();
+
+    let lower = word.to_lowercase();
+    let key = lower.trim_end_matches(|c: char| !c.is_alphanumeric());
+
+    if let Some(article) = overrides.get(key) {
+        return if article == "an" { "an" } else { "a" };
+    }
+
+    match starts_with_vowel_sound(word) {
+        true => "an",
+        false => "a",
+    }
+}
+
+// words starting with a silent `h`
+static SILENT_H: &[&str] = &["heir", "herb", "honest", "honor", "honour", "hour"];
+
+// letters whose names start with a vowel sound (`an F`, `an M`)
+static VOWEL_LETTERS: &str = "aefhilmnorsx";
+
+// consonants that can start a word together, so a word in capitals starting
+// with them is read as a word (`a SWORD`) rather than spelled out (`an FBI`)
+static ONSETS: &[&str] = &[
+    "bl", "br", "ch", "chr", "cl", "cr", "dr", "dw", "fl", "fr", "gl", "gn", "gr", "kl", "kn",
+    "kr", "ph", "pl", "pr", "ps", "sc", "sch", "scr", "sh", "shr", "sk", "sl", "sm", "sn", "sp",
+    "sph", "spl", "spr", "st", "str", "sw", "th", "thr", "tr", "tw", "wh", "wr",
+];
+
+fn starts_with_vowel_sound(word: &str) -> bool {
+    let Some(first) = word.chars().next() else {
+        return false;
+    };
+
+    if first.is_ascii_digit() {
+        return number_starts_with_vowel_sound(word);
+    }
+
+    let letters: String = word.chars().take_while(|c| c.is_alphabetic()).collect();
+    let lower = word.to_lowercase();
+
+    // acronyms and single letters are read letter by letter
+    if is_spelled_out(&letters) || letters.chars().count() == 1 {
+        return VOWEL_LETTERS.contains(first.to_ascii_lowercase());
+    }
+
+    let rest: Vec<char> = lower.chars().skip(1).collect();
+    let is_vowel = |c: Option<&char>| c.is_some_and(|c| "aeiou".contains(*c));
+
+    match first.to_ascii_lowercase() {
+        'h' => SILENT_H.iter().any(|h| lower.starts_with(h)),
+        // `one` and `once` start with a `w` sound, `onerous` doesn't
+        'o' => !(lower == "one" || lower.starts_with("one-") || lower.starts_with("once")),
+        // a `y` sound: `a European`, `a ewe`
+        'e' => !(lower.starts_with("eu") || lower.starts_with("ew")),
+        'u' if lower.starts_with("uni") => {
+            // `an uninformed`, `an unimportant`, `an unidentified` vs `a
+            // unicorn` and `a union`
+            let after: Vec<char> = lower.chars().skip(3).take(2).collect();
+
+            lower.starts_with("unid")
+                || (after.len() == 2 && !is_vowel(after.first()) && !is_vowel(after.get(1)))
+        }
+        // `an unusual`, `an umbrella` vs `a usual`, `a utensil`
+        'u' => lower.starts_with("un") || is_vowel(rest.first()) || !is_vowel(rest.get(1)),
+        'a' | 'i' => true,
+        _ => false,
+    }
+}
+
+/**
+ * Whether a number is spoken with a vowel sound: `eight`, `eleven`,
+ * `eighteen`, `eighty`, `eleven thousand` and so on.
+ */
+fn number_starts_with_vowel_sound(word: &str) -> bool {
+    let digits: String = word
+        .chars()
+        .take_while(|c| c.is_ascii_digit() || *c == ',')
+        .filter(|c| c.is_ascii_digit())
+        .collect();
+
+    // 11 and 18 only begin the spoken number when they lead a group of
+    // three digits, e.g. 11,000 but not 110 or 1,100
+    digits.starts_with('8')
+        || ((digits.starts_with("11") || digits.starts_with("18")) && digits.len() % 3 == 2)
+}
+
+/**
+ * Whether a word in capitals is an acronym read letter by letter: it has no
+ * vowels (`NPC`) or it can't be said as a word (`FBI`, `MRI`). Others are
+ * words in capitals, `a HORSE`.
+ */
+fn is_spelled_out(letters: &str) -> bool {
+    if letters.chars().count() < 2 || !letters.chars().all(|c| c.is_uppercase()) {
+        return false;
+    }
+
+    let lower = letters.to_lowercase();
+
+    match lower.find(['a', 'e', 'i', 'o', 'u', 'y']) {
+        Some(i) => i > 1 && !ONSETS.contains(&&lower[..i]),
+        None => true,
+    }
+}
+
+fn is_irregular_plural(word: &str, overrides: &BTreeMap<String, String>) -> bool {
+    overrides.values().any(|plural| plural == word)
+        || IRREGULAR.iter().any(|(_, plural)| *plural == word)
+}
+
+fn ends_with_vowel(word: &str) -> bool {
+    word.ends_with(['a', 'e', 'i', 'o', 'u'])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pluralize() {
+        let none = BTreeMap::new();
+        let cases = [
+            ("goblin", "goblins"),
+            ("box", "boxes"),
+            ("torch", "torches"),
+            ("ruby", "rubies"),
+            ("key", "keys"),
+            ("knife", "knives"),
+            ("wolf", "wolves"),
+            ("werewolf", "werewolves"),
+            ("half", "halves"),
+            ("shelf", "shelves"),
+            ("gulf", "gulfs"),
+            ("valve", "valves"),
+            ("leaf", "leaves"),
+            ("roof", "roofs"),
+            ("hero", "heroes"),
+            ("shoe", "shoes"),
+            ("toe", "toes"),
+            ("child", "children"),
+            ("swordsman", "swordsmen"),
+            ("sheep", "sheep"),
+            ("Elf", "Elves"),
+            ("ELF", "ELVES"),
+            ("NPC", "NPCs"),
+            ("long sword", "long swords"),
+            ("potion of healing", "potions of healing"),
+        ];
+
+        for (singular, plural) in cases {
+            assert_eq!(pluralize(singular, &none), plural);
+            assert_eq!(singularize(plural, &none), singular);
+        }
+
+        assert_eq!(pluralize("axis", &none), "axes");
+        assert_eq!(pluralize("children", &none), "children");
+        assert_eq!(singularize("cactus", &none), "cactus");
+        assert_eq!(pluralize("a", &none), "as");
+        assert_eq!(pluralize("3", &none), "3");
+    }
+
+    #[test]
+    fn test_indefinite_article() {
+        let none = BTreeMap::new();
+        let corpus = [
+            ("apple", "an"),
+            ("banana", "a"),
+            ("Elf", "an"),
+            ("Orc", "an"),
+            ("hour", "an"),
+            ("Honest man", "an"),
+            ("heir", "an"),
+            ("horse", "a"),
+            ("European", "a"),
+            ("ewe", "a"),
+            ("egg", "an"),
+            ("unicorn", "a"),
+            ("union", "a"),
+            ("unit", "a"),
+            ("uninformed guess", "an"),
+            ("unimportant detail", "an"),
+            ("unidentified object", "an"),
+            ("unusual hat", "an"),
+            ("umbrella", "an"),
+            ("usual suspect", "a"),
+            ("utensil", "a"),
+            ("urn", "an"),
+            ("one-eyed giant", "a"),
+            ("onerous task", "an"),
+            ("8-foot pole", "an"),
+            ("80 coins", "an"),
+            ("11th hour", "an"),
+            ("18 year old", "an"),
+            ("110 pounds", "a"),
+            ("11,000 gold", "an"),
+            ("1,100 gold", "a"),
+            ("7 swords", "a"),
+            ("NPC", "an"),
+            ("FBI agent", "an"),
+            ("UFO", "a"),
+            ("RPG", "an"),
+            ("DM", "a"),
+            ("X", "an"),
+            ("HORSE", "a"),
+            ("SWORD", "a"),
+            ("HOUR", "an"),
+            ("ORC", "an"),
+            ("MRI scan", "an"),
+            ("NASA probe", "a"),
+            ("HP", "an"),
+            ("\"easy\" job", "an"),
+            ("yak", "a"),
+        ];
+
+        for (phrase, article) in corpus {
+            assert_eq!(indefinite_article(phrase, &none), article, "{}", phrase);
+        }
+
+        let overrides = BTreeMap::from([("historic".to_string(), "an".to_string())]);
+
+        assert_eq!(indefinite_article("historic moment", &overrides), "an");
+        assert_eq!(indefinite_article("Historic moment", &overrides), "an");
+    }
+
+    #[test]
+    fn test_overrides() {
+        let overrides = BTreeMap::from([("cyclops".to_string(), "cyclopes".to_string())]);
+
+        assert_eq!(pluralize("Cyclops", &overrides), "Cyclopes");
+        assert_eq!(singularize("cyclopes", &overrides), "cyclops");
+        assert_eq!(pluralize("cyclops", &BTreeMap::new()), "cyclopses");
+    }
+}
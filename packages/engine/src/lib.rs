@@ -1,11 +1,22 @@
 #![warn(unused_extern_crates)]
 use sha2::{Digest, Sha256};
+use table_collection::TableCollection;
 use utils::set_panic_hook;
 use wasm_bindgen::prelude::*;
 use web_sys::js_sys::JsString;
 
+pub mod analysis;
+pub mod cooldown;
+pub mod custom_filter;
+pub mod deck;
+pub mod diagnostic;
+pub mod dice;
+pub mod inflection;
 mod nom_parser;
+pub mod session;
 pub mod table_collection;
+mod text;
+pub mod trace;
 mod utils;
 
 // Use `wee_alloc` as the global allocator.
@@ -21,3 +32,19 @@ pub fn table_hash(raw: JsString) -> JsString {
 
     JsString::from(hex::encode(hash))
 }
+
+/**
+ * Parses `raw` and returns any problems found as `Diagnostic` objects, or an
+ * empty array when the definitions are valid.
+ */
+#[wasm_bindgen]
+pub fn parse_diagnostics(raw: JsString) -> Vec<JsValue> {
+    set_panic_hook();
+
+    let raw: String = raw.into();
+
+    TableCollection::diagnostics_for(&raw)
+        .iter()
+        .map(|d| serde_wasm_bindgen::to_value(d).unwrap())
+        .collect()
+}
@@ -3,7 +3,9 @@
 extern crate log;
 
 use clap::Parser;
-use engine::table_collection::TableCollection;
+use engine::analysis::{Enumeration, TableAnalysis};
+use engine::session::Session;
+use engine::table_collection::{TableCollection, TableError};
 use std::sync::LazyLock;
 
 use std::fs;
@@ -19,6 +21,36 @@ struct Args {
 
     #[arg(short, long, default_value_t = 10)]
     count: usize,
+
+    #[arg(short, long)]
+    seed: Option<u64>,
+
+    #[arg(short, long)]
+    analyze: bool,
+
+    #[arg(short, long)]
+    enumerate: bool,
+
+    // a JSON file keeping the cards drawn from `mode: deck` tables between
+    // runs, created if it doesn't exist
+    #[arg(long)]
+    deck_state: Option<String>,
+
+    #[arg(long)]
+    reshuffle: bool,
+
+    #[arg(long)]
+    remaining: bool,
+
+    // leave out the results of the table's latest N picks, overriding its
+    // `cooldown` attribute
+    #[arg(long)]
+    cooldown: Option<usize>,
+
+    // a JSON file to resume a session from (RNG, decks and history) and save
+    // it to afterwards, created if it doesn't exist
+    #[arg(long)]
+    session: Option<String>,
 }
 
 fn main() {
@@ -35,15 +67,135 @@ fn main() {
 
     let args = Args::parse();
 
-    if let Ok(tabol) = TableCollection::new(TABLE_DEF.trim()) {
-        let table_name = args.table.unwrap_or(args.definition);
+    let tabol = TableCollection::parse_recovering(TABLE_DEF.trim());
+
+    for diagnostic in tabol.diagnostics() {
+        eprintln!("error: {diagnostic}");
+    }
+
+    let table_name = args.table.unwrap_or(args.definition);
+
+    debug!("Table IDs: {:?}", tabol.table_metadata());
+
+    if args.analyze {
+        match tabol.analyze(table_name.as_str()) {
+            Ok(analysis) => print_analysis(&analysis),
+            Err(e) => eprintln!("error: {e}"),
+        };
+
+        return;
+    }
+
+    if args.enumerate {
+        match tabol.enumerate(table_name.as_str()) {
+            Ok(enumeration) => print_enumeration(&enumeration),
+            Err(e) => eprintln!("error: {e}"),
+        };
+
+        return;
+    }
+
+    let saved = args
+        .session
+        .as_ref()
+        .and_then(|p| fs::read_to_string(p).ok());
 
-        debug!("Table IDs: {:?}", tabol.table_metadata());
+    let mut session = match saved {
+        Some(json) => match Session::from_json(tabol, &json) {
+            Ok(session) => session,
+            Err(e) => {
+                eprintln!("error: {e}");
+                return;
+            }
+        },
+        None => Session::new(tabol, args.seed),
+    };
+
+    session.set_cooldown(args.cooldown);
+
+    if let Some(state) = args
+        .deck_state
+        .as_ref()
+        .and_then(|p| fs::read_to_string(p).ok())
+    {
+        match serde_json::from_str(&state) {
+            Ok(state) => session.tables().restore_decks(state),
+            Err(e) => eprintln!("error: invalid deck state: {e}"),
+        }
+    }
+
+    if args.reshuffle {
+        session.reshuffle(table_name.as_str());
+    }
 
-        if let Ok(results) = tabol.gen_many(table_name.as_str(), args.count) {
-            for result in results {
-                println!("{}\n", result);
+    if args.remaining {
+        match session.tables().remaining_cards(table_name.as_str()) {
+            Ok(cards) => {
+                for card in cards.iter() {
+                    println!("{}", card.raw);
+                }
+
+                println!("\n{} left in the deck", cards.len());
+            }
+            Err(e) => eprintln!("error: {e}"),
+        };
+    } else {
+        match session.gen_many(table_name.as_str(), args.count) {
+            Ok(results) => {
+                for result in results {
+                    println!("{}\n", result);
+                }
             }
+            Err(e) => eprintln!("error: {e}"),
         };
     }
+
+    if let Some(path) = args.deck_state {
+        let state = serde_json::to_string_pretty(&session.tables().deck_state()).unwrap();
+
+        if let Err(e) = fs::write(&path, state) {
+            eprintln!("error: failed to save deck state to {path}: {e}");
+        }
+    }
+
+    if let Some(path) = args.session {
+        let saved = session.to_json().and_then(|json| {
+            fs::write(&path, json).map_err(|e| TableError::CallError(e.to_string()))
+        });
+
+        if let Err(e) = saved {
+            eprintln!("error: failed to save session to {path}: {e}");
+        }
+    }
+}
+
+fn print_analysis(analysis: &TableAnalysis) {
+    for rule in analysis.rules.iter() {
+        println!("{:>7.3}%  {}", rule.probability * 100.0, rule.raw);
+
+        for outcome in rule.outcomes.iter().flatten() {
+            println!("{:>7.3}%    {}", outcome.probability * 100.0, outcome.text);
+        }
+    }
+}
+
+fn print_enumeration(enumeration: &Enumeration) {
+    match &enumeration.outcomes {
+        Some(outcomes) => {
+            for outcome in outcomes {
+                println!("{:>7.3}%  {}", outcome.probability * 100.0, outcome.text);
+            }
+
+            println!("\n{} distinct outputs", outcomes.len());
+        }
+        None if enumeration.size_estimate.is_infinite() => {
+            println!(
+                "Infinitely many outputs, the table can interpolate itself or repeat without limit"
+            )
+        }
+        None => println!(
+            "Too many outputs to list, roughly {:e}",
+            enumeration.size_estimate
+        ),
+    }
 }
@@ -1,36 +1,160 @@
 use nom::{
-    bytes::complete::{take_until, take_while, take_while1},
-    character::complete::{char, digit1, line_ending, not_line_ending},
-    combinator::{map_parser, opt},
-    error::{make_error, ParseError},
-    multi::{fold_many1, many0, many1, separated_list1},
+    bytes::complete::{take_till1, take_while, take_while1},
+    character::complete::{
+        alpha1, char, digit1, line_ending, not_line_ending, one_of, space0, space1,
+    },
+    combinator::{eof, map_parser, not, opt, recognize},
+    error::{ErrorKind, FromExternalError, ParseError},
+    multi::{fold_many0, fold_many1, many0, many1, separated_list0, separated_list1},
     number::complete::float,
     sequence::{pair, separated_pair, tuple},
-    IResult, Parser,
+    IResult, InputTake, Parser,
 };
 use nom_locate::LocatedSpan;
 use nom_supreme::{error::ErrorTree, tag::complete::tag};
 use nom_supreme::{final_parser::final_parser, parser_ext::ParserExt};
-
-use crate::table_collection::{FilterOp, Rule, RuleInst, TableDefinition};
+use std::collections::BTreeMap;
+use thiserror::Error;
+
+use crate::analysis::expr_distribution;
+use crate::cooldown::RECENT_LIMIT;
+use crate::custom_filter;
+use crate::dice::{Dice, DiceExpr, Keep, Operator, DICE_LIMIT};
+use crate::inflection::Inflections;
+use crate::table_collection::{
+    Comment, Condition, FilterOp, Quantity, Rule, RuleInst, TableDefinition, TableMode, COUNT_LIMIT,
+};
 
 pub type Span<'a> = LocatedSpan<&'a str>;
 
 type ParserResult<'a, T> = IResult<Span<'a>, T, ErrorTree<Span<'a>>>;
 
-enum AttrValue<'a> {
-    Text(Span<'a>),
-    Bool(bool),
+/**
+ * An error raised by our own validation during parsing (as opposed to a
+ * combinator failing to match), its message is shown to authors as-is.
+ */
+#[derive(Debug, Error)]
+#[error("{0}")]
+struct SyntaxError(String);
+
+fn syntax_error<T>(location: Span, message: impl Into<String>) -> ParserResult<T> {
+    Err(nom::Err::Failure(ErrorTree::from_external_error(
+        location,
+        ErrorKind::Fail,
+        SyntaxError(message.into()),
+    )))
 }
 
 // --------- Tabol ---------
 pub(crate) fn parse_tables(input: Span) -> Result<Vec<TableDefinition>, ErrorTree<Span>> {
     final_parser(
-        separated_list1(pair(line_ending, line_ending), table)
-            .context("Expected 1 or more table definitions"),
+        pair(
+            separated_list1(pair(line_ending, line_ending), table)
+                .context("Expected 1 or more table definitions"),
+            trailing_comments,
+        )
+        .map(|(mut tables, comments)| {
+            if let Some(last) = tables.last_mut() {
+                last.comments.extend(comments);
+            }
+
+            tables
+        }),
     )(input)
 }
 
+// comments after the last table in the source, separated from it by blank lines
+fn trailing_comments(input: Span) -> ParserResult<Vec<Comment>> {
+    many0(comment.preceded_by(many1(line_ending)))
+        .terminated(many0(line_ending))
+        .parse(input)
+}
+
+/**
+ * Parses as many tables as possible, skipping ahead to the next table
+ * boundary (the next blank line) whenever a table fails to parse. Returns the
+ * valid tables along with one error per broken table.
+ */
+pub(crate) fn parse_tables_recovering(input: Span) -> (Vec<TableDefinition>, Vec<ErrorTree<Span>>) {
+    let mut tables: Vec<TableDefinition> = vec![];
+    let mut errors = vec![];
+    let mut remaining = skip_blank_lines(input);
+
+    while !remaining.is_empty() {
+        if let Ok((_, comments)) = separated_list0(many1(line_ending), comment)
+            .terminated(many0(line_ending))
+            .all_consuming()
+            .parse(remaining)
+        {
+            if let Some(last) = tables.last_mut() {
+                last.comments.extend(comments);
+            }
+
+            break;
+        }
+
+        let error = match table(remaining) {
+            Ok((rest, definition)) => {
+                tables.push(definition);
+
+                let next = skip_blank_lines(rest);
+
+                if next.is_empty() || rest.starts_with("\n\n") || rest.starts_with("\r\n\r\n") {
+                    remaining = next;
+                    continue;
+                }
+
+                ErrorTree::from_external_error(
+                    rest,
+                    ErrorKind::Fail,
+                    SyntaxError("Tables should be separated by a blank line".to_string()),
+                )
+            }
+            Err(nom::Err::Error(e)) | Err(nom::Err::Failure(e)) => e,
+            Err(nom::Err::Incomplete(_)) => {
+                ErrorTree::from_error_kind(remaining, ErrorKind::Complete)
+            }
+        };
+
+        errors.push(error);
+
+        match next_table_boundary(remaining) {
+            Some(next) => remaining = next,
+            None => break,
+        }
+    }
+
+    (tables, errors)
+}
+
+fn skip_blank_lines(input: Span) -> Span {
+    let trimmed = input.trim_start_matches(['\r', '\n']);
+
+    input.take_split(input.len() - trimmed.len()).0
+}
+
+/**
+ * Finds the start of the next table after the beginning of `input`, i.e. the
+ * first non-blank line (`---`, a pragma or a comment) following a blank line.
+ */
+fn next_table_boundary(input: Span) -> Option<Span> {
+    let mut offset = 0;
+    let mut after_blank_line = false;
+
+    for line in input.split_inclusive('\n') {
+        let is_blank = line.trim_end_matches(['\r', '\n']).is_empty();
+
+        if after_blank_line && !is_blank {
+            return Some(input.take_split(offset).0);
+        }
+
+        after_blank_line = is_blank;
+        offset += line.len();
+    }
+
+    None
+}
+
 /**
  * --------- Table ---------
  *
@@ -44,18 +168,51 @@ pub(crate) fn parse_tables(input: Span) -> Result<Vec<TableDefinition>, ErrorTre
  *
  */
 fn table(input: Span) -> ParserResult<TableDefinition> {
-    tuple((namespace_pragma.opt(), frontmatter, rules))
-        .context("Invalid table definition")
-        .map(|(namespace, frontmatter, rules)| {
-            TableDefinition::new(
-                frontmatter.id.to_string(),
-                namespace.map(|s| s.to_string()),
-                frontmatter.title.to_string(),
-                frontmatter.export.unwrap(),
-                rules,
-            )
-        })
-        .parse(input)
+    table_parts.context("Invalid table definition").parse(input)
+}
+
+fn table_parts(input: Span) -> ParserResult<TableDefinition> {
+    let (rest, (mut comments, namespace, frontmatter)) =
+        tuple((many0(comment_line), namespace_pragma.opt(), frontmatter)).parse(input)?;
+
+    let (rest, (rules, rule_comments)) = rules(rest)?;
+
+    let rules = match &frontmatter.roll {
+        Some((value, roll)) => ranged_rules(*value, roll, rules)?,
+        None => {
+            if let Some((key, _)) = rules.iter().find(|(_, rule)| rule.range.is_some()) {
+                return syntax_error(
+                    *key,
+                    "Ranges of rolls require a `roll` table attribute, e.g. `roll: d100`",
+                );
+            }
+
+            rules.into_iter().map(|(_, rule)| rule).collect()
+        }
+    };
+
+    comments.extend(frontmatter.comments);
+    comments.extend(rule_comments);
+
+    let mut table = TableDefinition::new(
+        frontmatter.id.to_string(),
+        namespace.map(|s| s.to_string()),
+        frontmatter.title.to_string(),
+        frontmatter.export,
+        rules,
+    )
+    .with_metadata(frontmatter.metadata)
+    .with_comments(comments)
+    .with_offset(frontmatter.id.location_offset())
+    .with_inflections(frontmatter.inflections)
+    .with_mode(frontmatter.mode)
+    .with_cooldown(frontmatter.cooldown);
+
+    if let Some((_, roll)) = frontmatter.roll {
+        table = table.with_roll(roll);
+    }
+
+    Ok((rest, table))
 }
 
 fn namespace_pragma(input: Span) -> ParserResult<Span> {
@@ -67,104 +224,313 @@ fn namespace_pragma(input: Span) -> ParserResult<Span> {
 
 struct Frontmatter<'a> {
     pub title: &'a str,
-    pub id: &'a str,
-    pub export: Option<bool>,
+    pub id: Span<'a>,
+    pub export: bool,
+    // the die rolled to pick a row, with the attribute value for diagnostics
+    pub roll: Option<(Span<'a>, DiceExpr)>,
+    pub inflections: Inflections,
+    pub mode: TableMode,
+    pub cooldown: usize,
+    pub metadata: BTreeMap<String, String>,
+    pub comments: Vec<Comment>,
 }
 
 /**
- * Right now this parser requires that the frontmatter have `id` and `title` in
- * that order optionally followed by `export`.
+ * Frontmatter is a set of `key: value` attributes in any order. `id` and
+ * `title` are required, `export`, `roll`, `mode`, `cooldown`, `plurals` and
+ * `articles` are optional and any other keys are kept as free-form metadata
+ * on the table.
  */
 fn frontmatter(input: Span) -> ParserResult<Frontmatter> {
-    let (input, mut attrs): (Span, Vec<(&str, AttrValue)>) =
-        fold_many1(frontmatter_attr, Vec::new, |mut acc: Vec<_>, (k, v)| {
-            acc.push((*k, v));
-            acc
-        })
-        .map_res(|mut acc| {
-            acc.reverse();
-            Ok::<Vec<(&str, AttrValue)>, ErrorTree<Span>>(acc)
-        })
-        .delimited_by(
-            pair(tag("---"), line_ending).context("Table attributes should be enclosed in `---`"),
-        )
-        .context("Invalid table attributes")
-        .parse(input)?;
+    let (rest, (fence, lines)) = pair(
+        frontmatter_fence,
+        many0(
+            comment_line
+                .map(FrontmatterLine::Comment)
+                .or(frontmatter_attr.map(|(k, v)| FrontmatterLine::Attr(k, v)))
+                .preceded_by(not(frontmatter_fence)),
+        ),
+    )
+    .terminated(frontmatter_fence)
+    .context("Invalid table attributes")
+    .parse(input)?;
+
+    let mut seen: Vec<Span> = vec![];
+    let mut id = None;
+    let mut title = None;
+    let mut export = false;
+    let mut roll = None;
+    // with the attribute value for diagnostics
+    let mut mode = None;
+    let mut cooldown = None;
+    let mut inflections = Inflections::default();
+    let mut metadata = BTreeMap::new();
+    let mut comments = vec![];
+
+    for line in lines {
+        let (key, value) = match line {
+            FrontmatterLine::Attr(key, value) => (key, value),
+            FrontmatterLine::Comment(comment) => {
+                comments.push(comment);
+                continue;
+            }
+        };
 
-    // XXX: this sucks
-    // - allows "title: ", but not "title:"
-    // TODO: arbitary frontmatter???
-    let id = if let Some(("id", AttrValue::Text(s))) = attrs.pop() {
-        *s
-    } else {
-        return Err(nom::Err::Failure(make_error(
-            input,
-            // XXX: not great display
-            nom::error::ErrorKind::Fail,
-        )));
-    };
+        if seen.iter().any(|k| k.fragment() == key.fragment()) {
+            return syntax_error(key, format!("Duplicate table attribute `{}`", key));
+        }
 
-    let title = if let Some(("title", AttrValue::Text(s))) = attrs.pop() {
-        *s
-    } else {
-        return Err(nom::Err::Failure(make_error(
-            input,
-            // XXX: not great display
-            nom::error::ErrorKind::Fail,
-        )));
+        seen.push(key);
+
+        match *key.fragment() {
+            "id" => id = Some(attr_value(value, ident, "`id` must be a valid identifier")?),
+            "title" => {
+                title = Some(*attr_value(
+                    value,
+                    literal,
+                    "`title` contains invalid characters",
+                )?)
+            }
+            "export" => {
+                export = attr_value(
+                    value,
+                    tag("true").or(tag("false")),
+                    "`export` must be `true` or `false`",
+                )?
+                .fragment()
+                    == &"true"
+            }
+            "roll" => {
+                let expr = attr_value(
+                    value,
+                    dice_expr,
+                    "`roll` must be a dice roll, e.g. `d100` or `2d6`",
+                )?;
+
+                if !expr.has_dice() || expr.bounds().is_none() {
+                    return syntax_error(
+                        value,
+                        "`roll` must be a dice roll with a highest possible result, e.g. `d100` or `2d6`",
+                    );
+                }
+
+                roll = Some((value, expr));
+            }
+            "mode" => {
+                let parsed = attr_value(
+                    value,
+                    tag("random")
+                        .value(TableMode::Random)
+                        .or(tag("deck").value(TableMode::Deck)),
+                    "`mode` must be `random` or `deck`",
+                )?;
+
+                mode = Some((value, parsed));
+            }
+            "cooldown" => {
+                let parsed = attr_value(
+                    value,
+                    digit1
+                        .map_res(|s: Span| s.parse::<usize>())
+                        .verify(|cooldown| *cooldown <= RECENT_LIMIT),
+                    &format!(
+                        "`cooldown` must be a number of picks up to {}, e.g. `3`",
+                        RECENT_LIMIT
+                    ),
+                )?;
+
+                cooldown = Some((value, parsed));
+            }
+            "plurals" => {
+                inflections.plurals = attr_value(
+                    value,
+                    separated_list1(tag(",").terminated(space0), irregular_plural),
+                    "`plurals` must be a list of irregular plurals, e.g. `cyclops/cyclopes, ox/oxen`",
+                )?
+                .into_iter()
+                .collect();
+            }
+            "articles" => {
+                inflections.articles = attr_value(
+                    value,
+                    separated_list1(tag(",").terminated(space0), article_override),
+                    "`articles` must be a list of words with their article, e.g. `an herb, a unicorn`",
+                )?
+                .into_iter()
+                .collect();
+            }
+            _ => {
+                metadata.insert(key.to_string(), value.to_string());
+            }
+        }
+    }
+
+    let Some(id) = id else {
+        return syntax_error(fence, "Missing required table attribute `id`");
     };
 
-    let export = if let Some(("export", AttrValue::Bool(b))) = attrs.pop() {
-        Some(b)
-    } else {
-        Some(false)
+    let Some(title) = title else {
+        return syntax_error(fence, "Missing required table attribute `title`");
     };
 
-    Ok((input, Frontmatter { id, title, export }))
+    if let (Some((value, TableMode::Deck)), Some(_)) = (mode, &roll) {
+        return syntax_error(
+            value,
+            "`mode: deck` draws rows like cards, so it can't be combined with `roll`",
+        );
+    }
+
+    if let Some((value, _)) = cooldown {
+        if roll.is_some() {
+            return syntax_error(
+                value,
+                "`cooldown` leaves out rows picked by weight, so it can't be combined with `roll`",
+            );
+        }
+
+        if let Some((_, TableMode::Deck)) = mode {
+            return syntax_error(
+                value,
+                "`mode: deck` never repeats a row before reshuffling, so it doesn't need a `cooldown`",
+            );
+        }
+    }
+
+    Ok((
+        rest,
+        Frontmatter {
+            id,
+            title,
+            export,
+            roll,
+            inflections,
+            mode: mode.map(|(_, mode)| mode).unwrap_or_default(),
+            cooldown: cooldown.map(|(_, cooldown)| cooldown).unwrap_or_default(),
+            metadata,
+            comments,
+        },
+    ))
+}
+
+// `cyclops/cyclopes`, lowercased for lookups
+fn irregular_plural(input: Span) -> ParserResult<(String, String)> {
+    separated_pair(override_word, tag("/"), override_word)
+        .map(|(singular, plural)| (singular.to_lowercase(), plural.to_lowercase()))
+        .parse(input)
+}
+
+// `an herb`, keyed by the lowercase word
+fn article_override(input: Span) -> ParserResult<(String, String)> {
+    separated_pair(tag("an").or(tag("a")), space1, override_word)
+        .map(|(article, word)| (word.to_lowercase(), article.to_string()))
+        .parse(input)
+}
+
+fn override_word(input: Span) -> ParserResult<Span> {
+    take_while1(|c: char| c.is_alphanumeric() || c == '\'' || c == '-').parse(input)
+}
+
+enum FrontmatterLine<'a> {
+    Attr(Span<'a>, Span<'a>),
+    Comment(Comment),
 }
 
-fn frontmatter_attr(input: Span) -> ParserResult<(Span, AttrValue)> {
-    id_attr
-        .or(title_attr)
-        .or(export_attr)
-        .context("Table attributes should be formatted like `name: value`")
+fn frontmatter_fence(input: Span) -> ParserResult<Span> {
+    tag("---")
         .terminated(line_ending)
+        .context("Table attributes should be enclosed in `---`")
         .parse(input)
 }
 
-fn id_attr(input: Span) -> ParserResult<(Span, AttrValue)> {
+// `name: value`
+fn frontmatter_attr(input: Span) -> ParserResult<(Span, Span)> {
     separated_pair(
-        tag("id").context("Table attributes can only contain alphanumeric characters"),
-        tag(": ").context("Missing table attribute separator, expected `:`"),
-        ident.map_res(|s| Ok::<AttrValue, ErrorTree<Span>>(AttrValue::Text(s))),
+        ident.context("Table attributes can only contain alphanumeric characters"),
+        pair(tag(":"), space0).context("Missing table attribute separator, expected `:`"),
+        not_line_ending.map(trim_end),
     )
+    .terminated(line_ending)
+    .cut()
+    .context("Table attributes should be formatted like `name: value`")
     .parse(input)
 }
 
-fn title_attr(input: Span) -> ParserResult<(Span, AttrValue)> {
-    separated_pair(
-        tag("title").context("Table attributes can only contain alphanumeric characters"),
-        tag(": ").context("Missing table attribute separator, expected `:`"),
-        map_parser(not_line_ending, literal)
-            .map_res(|s| Ok::<AttrValue, ErrorTree<Span>>(AttrValue::Text(s))),
-    )
-    .parse(input)
+/**
+ * Parses the whole of an attribute value with `parser`, failing with
+ * `message` at the value's location.
+ */
+fn attr_value<'a, T>(
+    value: Span<'a>,
+    parser: impl Parser<Span<'a>, T, ErrorTree<Span<'a>>>,
+    message: &str,
+) -> Result<T, nom::Err<ErrorTree<Span<'a>>>> {
+    match parser.all_consuming().parse(value) {
+        Ok((_, parsed)) => Ok(parsed),
+        Err(_) => syntax_error(value, message).map(|(_, parsed)| parsed),
+    }
 }
 
-fn export_attr(input: Span) -> ParserResult<(Span, AttrValue)> {
-    separated_pair(
-        tag("export").context("Table attributes can only contain alphanumeric characters"),
-        tag(": ").context("Missing table attribute separator, expected `:`"),
-        tag("true")
-            .or(tag("false"))
-            .map_res(|b: Span| Ok::<AttrValue, ErrorTree<Span>>(AttrValue::Bool(*b == "true"))),
-    )
-    .parse(input)
+fn trim_end(span: Span) -> Span {
+    span.take(span.trim_end().len())
 }
 
 // --------- Rules ---------
-fn rules(input: Span) -> ParserResult<Vec<Rule>> {
-    separated_list1(line_ending, rule_line).parse(input)
+
+// a rule with its line, for diagnostics
+type RuleLine<'a> = (Span<'a>, Rule);
+
+fn rules(input: Span) -> ParserResult<(Vec<RuleLine>, Vec<Comment>)> {
+    // a non-blank line following a rule must be another rule, cutting here
+    // reports the broken rule rather than a confusing error further up
+    let (rest, lines) = separated_list1(
+        line_ending.terminated(not(line_ending).and(not(eof))),
+        comment.map(|c| (None, Some(c))).or(commented_rule_line
+            .with_recognized()
+            .cut()
+            .map(|(line, (r, c))| (Some((line, r)), c))),
+    )
+    .parse(input)?;
+
+    let mut rules = vec![];
+    let mut comments = vec![];
+
+    for (rule, comment) in lines {
+        rules.extend(rule);
+        comments.extend(comment);
+    }
+
+    if rules.is_empty() {
+        return syntax_error(input, "Expected at least one rule");
+    }
+
+    Ok((rest, (rules, comments)))
+}
+
+/**
+ * A rule line, optionally followed by a trailing comment. The comment is
+ * split off before parsing the rule so that it doesn't end up in its text.
+ */
+fn commented_rule_line(input: Span) -> ParserResult<(Rule, Option<Comment>)> {
+    let (rest, line) = not_line_ending(input)?;
+
+    let (content, comment_text) = match trailing_comment_start(&line) {
+        Some(index) => {
+            let (comment_text, content) = line.take_split(index);
+            (trim_end(content), Some(comment_text))
+        }
+        None => (line, None),
+    };
+
+    let (_, rule) = rule_line.all_consuming().parse(content)?;
+    let trailing = match comment_text {
+        Some(text) => Some(Comment {
+            trailing: true,
+            ..comment.parse(text)?.1
+        }),
+        None => None,
+    };
+
+    Ok((rest, (rule, trailing)))
 }
 
 fn rule_line(input: Span) -> ParserResult<Rule> {
@@ -173,20 +539,274 @@ fn rule_line(input: Span) -> ParserResult<Rule> {
     map_parser(
         not_line_ending,
         separated_pair(
-            float.context("Invalid rule weight, expected an integer or float"),
+            rule_range
+                .map(|(lo, hi)| ((hi - lo + 1) as f32, Some((lo, hi))))
+                .or(float.map(|weight| (weight, None)))
+                .context("Invalid rule weight, expected an integer or float"),
             tag(": ").context("Missing rule separator, expected `:`"),
             rule,
         )
         .context("Rule should start with a weight, followed by a `:` and then the rule text")
-        .map(|(weight, (raw, parts))| Rule {
+        .map(|((weight, range), (raw, parts))| Rule {
             raw: (*raw).to_string(),
             weight,
+            range,
             parts,
         }),
     )
     .parse(input)
 }
 
+/**
+ * A range of rolls such as `01-05`. On a d100 `00` means 100, as in `96-00`.
+ */
+fn rule_range(input: Span) -> ParserResult<(i64, i64)> {
+    let roll = || {
+        digit1.map_res(|s: Span| match *s.fragment() {
+            "00" => Ok(100),
+            s => s.parse::<i64>(),
+        })
+    };
+
+    separated_pair(roll(), tag("-"), roll()).parse(input)
+}
+
+/**
+ * Checks the rows of a table with a `roll`: each row must be a roll (`7`) or
+ * range of rolls (`01-05`) that the die can produce, rows can't overlap and
+ * every possible roll must have a row. Each rule's weight becomes the chance
+ * of rolling its range.
+ */
+fn ranged_rules<'a>(
+    roll_value: Span<'a>,
+    roll: &DiceExpr,
+    rules: Vec<RuleLine<'a>>,
+) -> Result<Vec<Rule>, nom::Err<ErrorTree<Span<'a>>>> {
+    let (min, max) = roll.bounds().unwrap_or_default();
+
+    // every possible roll with its chance, in ascending order. Rows are
+    // weighted by their exact chance, so rolls with too many possible results
+    // to compute it aren't allowed
+    let Some(rolls) = expr_distribution(roll) else {
+        return syntax_error(
+            roll_value,
+            format!(
+                "`{}` has too many possible results to work out the chance of each row",
+                roll
+            ),
+        )
+        .map(|(_, rules)| rules);
+    };
+
+    let rolls: Vec<(i64, f64)> = rolls.into_iter().filter(|(_, p)| *p > 0.0).collect();
+
+    // the chance of rolling `lo..=hi`
+    let chance_of = |lo: i64, hi: i64| -> f64 {
+        let start = rolls.partition_point(|(r, _)| *r < lo);
+        let end = rolls.partition_point(|(r, _)| *r <= hi);
+
+        rolls[start..end].iter().map(|(_, p)| p).sum()
+    };
+
+    let mut ranged: Vec<RuleLine> = vec![];
+
+    for (line, mut rule) in rules {
+        let (lo, hi) = match rule.range {
+            Some(range) => range,
+            None if rule.weight.fract() == 0.0 => (rule.weight as i64, rule.weight as i64),
+            None => {
+                return syntax_error(
+                    line,
+                    "Rows must be a roll (`7`) or a range of rolls (`01-05`) when the table has a `roll`",
+                )
+                .map(|(_, rules)| rules)
+            }
+        };
+
+        if lo > hi {
+            return syntax_error(line, format!("Range `{}-{}` ends before it starts", lo, hi))
+                .map(|(_, rules)| rules);
+        }
+
+        if lo < min || hi > max {
+            return syntax_error(
+                line,
+                format!(
+                    "`{}` is outside the possible rolls of `{}` ({}-{})",
+                    format_range(lo, hi),
+                    roll,
+                    min,
+                    max
+                ),
+            )
+            .map(|(_, rules)| rules);
+        }
+
+        let chance = chance_of(lo, hi);
+
+        if chance == 0.0 {
+            return syntax_error(
+                line,
+                format!(
+                    "`{}` can never be rolled on `{}`",
+                    format_range(lo, hi),
+                    roll
+                ),
+            )
+            .map(|(_, rules)| rules);
+        }
+
+        rule.range = Some((lo, hi));
+        rule.weight = chance as f32;
+        ranged.push((line, rule));
+    }
+
+    // rows in order of their ranges, so each can only overlap the next one
+    // and the rolls between neighbouring rows are the ones without a row
+    let mut sorted: Vec<&RuleLine> = ranged.iter().collect();
+    sorted.sort_by_key(|(_, rule)| rule.range);
+
+    for pair in sorted.windows(2) {
+        let (_, first_hi) = pair[0].1.range.unwrap_or_default();
+        let (second_lo, _) = pair[1].1.range.unwrap_or_default();
+
+        if second_lo <= first_hi {
+            // reported on whichever row comes later in the source
+            let (earlier, later) = if pair[0].0.location_offset() < pair[1].0.location_offset() {
+                (pair[0], pair[1])
+            } else {
+                (pair[1], pair[0])
+            };
+            let (lo, hi) = later.1.range.unwrap_or_default();
+
+            return syntax_error(
+                later.0,
+                format!(
+                    "`{}` overlaps the row on line {}",
+                    format_range(lo, hi),
+                    earlier.0.location_line()
+                ),
+            )
+            .map(|(_, rules)| rules);
+        }
+    }
+
+    let mut gaps: Vec<(i64, i64)> = vec![];
+    let mut next = min;
+
+    for (_, rule) in sorted.iter() {
+        let (lo, hi) = rule.range.unwrap_or_default();
+
+        if next < lo {
+            gaps.push((next, lo - 1));
+        }
+
+        next = hi.saturating_add(1);
+    }
+
+    if next <= max {
+        gaps.push((next, max));
+    }
+
+    // only the rolls that can actually come up in each gap need a row
+    let mut uncovered: Vec<(i64, i64)> = vec![];
+
+    for (lo, hi) in gaps {
+        let start = rolls.partition_point(|(r, _)| *r < lo);
+        let end = rolls.partition_point(|(r, _)| *r <= hi);
+
+        for (r, _) in rolls[start..end].iter() {
+            match uncovered.last_mut() {
+                Some((_, hi)) if *hi + 1 == *r => *hi = *r,
+                _ => uncovered.push((*r, *r)),
+            }
+        }
+    }
+
+    if !uncovered.is_empty() {
+        let uncovered: Vec<String> = uncovered
+            .iter()
+            .map(|(lo, hi)| format_range(*lo, *hi))
+            .collect();
+
+        return syntax_error(
+            roll_value,
+            format!("No row for rolls of {} on `{}`", uncovered.join(", "), roll),
+        )
+        .map(|(_, rules)| rules);
+    }
+
+    Ok(ranged.into_iter().map(|(_, rule)| rule).collect())
+}
+
+fn format_range(lo: i64, hi: i64) -> String {
+    if lo == hi {
+        lo.to_string()
+    } else {
+        format!("{}-{}", lo, hi)
+    }
+}
+
+// --------- Comments ---------
+
+// `# comment` or `// comment`, possibly indented
+fn comment(input: Span) -> ParserResult<Comment> {
+    pair(tag("#").or(tag("//")), not_line_ending)
+        .recognize()
+        .preceded_by(space0)
+        .map(|text: Span| Comment {
+            text: text.to_string(),
+            offset: text.location_offset(),
+            line: text.location_line(),
+            column: text.get_utf8_column(),
+            trailing: false,
+        })
+        .parse(input)
+}
+
+fn comment_line(input: Span) -> ParserResult<Comment> {
+    comment.terminated(line_ending).parse(input)
+}
+
+/**
+ * Finds where a trailing comment starts in a rule line. Rule text often
+ * contains a lone `#` or `//` (`Room #5`, `http://`), so a trailing comment
+ * is marked with `##`, which must follow some rule text, be preceded by
+ * whitespace and can't be escaped or inside an interpolation.
+ */
+fn trailing_comment_start(line: &str) -> Option<usize> {
+    let mut depth = 0;
+    let mut in_quotes = false;
+    let mut escaped = false;
+    let mut previous = ' ';
+    let text_start = line.find(": ").map(|i| i + 2)?;
+
+    for (index, c) in line.char_indices().skip_while(|(i, _)| *i < text_start) {
+        let is_marker = line[index..].starts_with("##");
+
+        if escaped {
+            escaped = false;
+            previous = c;
+            continue;
+        }
+
+        match c {
+            '\\' => escaped = true,
+            '\'' if depth > 0 => in_quotes = !in_quotes,
+            '{' if !in_quotes => depth += 1,
+            '}' if !in_quotes && depth > 0 => depth -= 1,
+            '#' if is_marker && depth == 0 && previous.is_whitespace() && index > text_start => {
+                return Some(index);
+            }
+            _ => {}
+        }
+
+        previous = c;
+    }
+
+    None
+}
+
 // --------- Rule ---------
 pub fn rule(input: Span) -> ParserResult<(Span, Vec<RuleInst>)> {
     // Can't use `complete()` here because `many1` doesn't return `Incomplete`
@@ -196,6 +816,9 @@ pub fn rule(input: Span) -> ParserResult<(Span, Vec<RuleInst>)> {
           rule_dice_roll
           .or(rule_literal)
           .or(imported_rule_interpolation)
+          .or(rule_variable)
+          .or(rule_conditional)
+          .or(rule_choice)
           .or(rule_interpolation)
         )
         .context("Invalid rule text, expected a dice roll (`2d4`), an interpolation (`{other}`) or a literal")
@@ -203,53 +826,189 @@ pub fn rule(input: Span) -> ParserResult<(Span, Vec<RuleInst>)> {
         .parse(input)?;
 
     if !input.is_empty() {
-        return Err(nom::Err::Failure(make_error(
-            input,
-            nom::error::ErrorKind::Fail,
-        )));
+        return syntax_error(input, "Invalid rule text, expected a dice roll (`2d4`), an interpolation (`{other}`) or a literal");
     }
 
     Ok((input, parsed))
 }
 
+/**
+ * A dice expression in braces, e.g. `{2d6+3}`, `{4d6kh3}` or `{1d8+1d6-2}`.
+ * The expression must roll at least one die. Its total can be transformed
+ * (`{1d20|ordinal}`) and bound to a variable like an interpolation
+ * (`{1d4>$count}`).
+ */
 fn rule_dice_roll(input: Span) -> ParserResult<RuleInst> {
     tuple((
-        digit1.map_res(|s: Span| s.parse::<usize>()),
-        digit1
-            .map_res(|s: Span| s.parse::<usize>())
-            .preceded_by(tag("d")),
+        dice_expr.verify(DiceExpr::has_dice),
+        many0(transform_filter.preceded_by(tag("|"))),
+        binding.opt(),
     ))
-    .or(digit1
-        .map_res(|s: Span| s.parse::<usize>())
-        .preceded_by(tag("d"))
-        .map(|sides| (1, sides)))
     .preceded_by(tag("{"))
     .terminated(tag("}"))
     .context("dice roll literal")
-    .map(|(count, sides)| RuleInst::DiceRoll(count, sides))
+    .map(|(expr, filters, binding)| bound(RuleInst::DiceRoll(expr, filters), binding))
     .parse(input)
 }
 
-fn rule_literal(input: Span) -> ParserResult<RuleInst> {
-    // can't just do `take_until("{").or(not_line_ending)` or else we'll
-    // successfully parse "" which causes many1 to fail
-    map_parser(take_until("{").or(not_line_ending), literal)
-        .context("rule literal")
-        .map(|x| RuleInst::Literal(x.to_string()))
+// --------- Dice ---------
+
+pub(crate) fn dice_expr(input: Span) -> ParserResult<DiceExpr> {
+    let (input, first) = dice_term(input)?;
+
+    fold_many0(
+        pair(dice_operator("+-"), dice_term),
+        move || first.clone(),
+        |lhs, (op, rhs)| DiceExpr::BinaryOp(op, Box::new(lhs), Box::new(rhs)),
+    )
+    .parse(input)
+}
+
+fn dice_term(input: Span) -> ParserResult<DiceExpr> {
+    let (input, first) = dice_factor(input)?;
+
+    fold_many0(
+        pair(dice_operator("*/"), dice_factor),
+        move || first.clone(),
+        |lhs, (op, rhs)| DiceExpr::BinaryOp(op, Box::new(lhs), Box::new(rhs)),
+    )
+    .parse(input)
+}
+
+fn dice_factor(input: Span) -> ParserResult<DiceExpr> {
+    dice.map(DiceExpr::Dice)
+        .or(digit1
+            .map_res(|s: Span| s.parse::<i64>())
+            .map(DiceExpr::Number))
+        .or(dice_expr
+            .delimited_by(space0)
+            .preceded_by(tag("("))
+            .terminated(tag(")")))
         .parse(input)
 }
 
+fn dice_operator<'a>(
+    operators: &'static str,
+) -> impl FnMut(Span<'a>) -> ParserResult<'a, Operator> {
+    move |input| {
+        one_of(operators)
+            .delimited_by(space0)
+            .map(|op| match op {
+                '+' => Operator::Add,
+                '-' => Operator::Subtract,
+                '*' => Operator::Multiply,
+                _ => Operator::Divide,
+            })
+            .parse(input)
+    }
+}
+
+/**
+ * `NdM` (or `dM`), optionally exploding (`3d6!`) and keeping or dropping the
+ * highest or lowest dice (`4d6kh3`, `2d20kl1`, `4d6dl1`).
+ */
+fn dice(input: Span) -> ParserResult<Dice> {
+    let number = || digit1.map_res(|s: Span| s.parse::<usize>());
+
+    let (rest, (count, sides, explode, keep)) = tuple((
+        digit1.opt(),
+        digit1
+            .verify(|sides: &Span| sides.chars().any(|c| c != '0'))
+            .preceded_by(tag("d")),
+        tag("!").opt(),
+        tuple((
+            tag("kh")
+                .value(Keep::Highest as fn(usize) -> Keep)
+                .or(tag("kl").value(Keep::Lowest as fn(usize) -> Keep))
+                .or(tag("dh").value(Keep::DropHighest as fn(usize) -> Keep))
+                .or(tag("dl").value(Keep::DropLowest as fn(usize) -> Keep)),
+            number(),
+        ))
+        .opt(),
+    ))
+    .parse(input)?;
+
+    // rolling is proportional to the count, and dice are rolled as `u32`
+    let count = match count.map(|count| count.parse::<usize>()) {
+        None => 1,
+        Some(Ok(count)) if count <= DICE_LIMIT => count,
+        Some(_) => {
+            return syntax_error(
+                input,
+                format!(
+                    "Too many dice, at most {} can be rolled at once",
+                    DICE_LIMIT
+                ),
+            )
+        }
+    };
+
+    let sides = match sides.parse::<usize>() {
+        Ok(sides) if sides <= u32::MAX as usize => sides,
+        _ => {
+            return syntax_error(
+                sides,
+                format!("Too many sides, dice can have at most {}", u32::MAX),
+            )
+        }
+    };
+
+    Ok((
+        rest,
+        Dice {
+            count,
+            sides,
+            explode: explode.is_some(),
+            keep: keep.map(|(keep, n)| keep(n)),
+        },
+    ))
+}
+
+fn rule_literal(input: Span) -> ParserResult<RuleInst> {
+    // must consume at least one character or else `many1` in `rule` fails
+    fold_many1(
+        escape_sequence
+            .map(String::from)
+            .or(take_till1(|c| c == '{' || c == '\\').map(|s: Span| s.to_string())),
+        String::new,
+        |mut acc, s| {
+            acc.push_str(&s);
+            acc
+        },
+    )
+    .context("rule literal")
+    .map(RuleInst::Literal)
+    .parse(input)
+}
+
+// `\{`, `\}`, `\[`, `\]`, `\|`, `\'`, `\#`, `\\` or `\n`. Any other backslash is
+// literal text, e.g. in `\o/` or a Windows path
+fn escape_sequence(input: Span) -> ParserResult<char> {
+    let (rest, _) = char('\\').parse(input)?;
+
+    match rest.chars().next() {
+        Some(c @ ('{' | '}' | '[' | ']' | '|' | '\'' | '#' | '\\')) => {
+            Ok((rest.take_split(1).0, c))
+        }
+        Some('n') => Ok((rest.take_split(1).0, '\n')),
+        _ => Ok((rest, '\\')),
+    }
+}
+
 fn imported_rule_interpolation(input: Span) -> ParserResult<RuleInst> {
-    imported_pipeline
+    pair(imported_pipeline, binding.opt())
         .preceded_by(tag("{"))
         .terminated(tag("}"))
         .context("Invalid imported rule interpolation")
-        .map(|(ns, id, filters)| {
-            RuleInst::ExternalInterpolation(
-                ns.to_string(),
-                id.to_string(),
-                format!("{ns}/{id}").to_string(),
-                filters,
+        .map(|((ns, id, filters), binding)| {
+            bound(
+                RuleInst::ExternalInterpolation(
+                    ns.to_string(),
+                    id.to_string(),
+                    format!("{ns}/{id}").to_string(),
+                    filters,
+                ),
+                binding,
             )
         })
         .parse(input)
@@ -262,68 +1021,312 @@ fn imported_pipeline(input: Span) -> ParserResult<(Span, Span, Vec<FilterOp>)> {
 }
 
 fn rule_interpolation(input: Span) -> ParserResult<RuleInst> {
-    pipeline
+    pair(pipeline, binding.opt())
         .preceded_by(tag("{"))
         .terminated(tag("}"))
         .context("Invalid rule interpolation")
-        .map(|(s, filters)| RuleInst::Interpolation((*s).to_string(), filters))
+        .map(|((s, filters), binding)| {
+            bound(RuleInst::Interpolation((*s).to_string(), filters), binding)
+        })
         .parse(input)
 }
 
+/**
+ * An inline choice between alternatives, e.g. `{[red|blue|green]}`. Each
+ * option may have a weight (`{[3:red|1:blue]}`) and contain interpolations,
+ * and the choice accepts the same filters as an interpolation.
+ */
+fn rule_choice(input: Span) -> ParserResult<RuleInst> {
+    pair(
+        separated_list1(tag("|"), choice_option)
+            .preceded_by(tag("["))
+            .terminated(tag("]")),
+        filters,
+    )
+    .preceded_by(tag("{"))
+    .terminated(tag("}"))
+    .context("Invalid inline choice, expected e.g. `{[red|blue]}`")
+    .map(|(options, filters)| RuleInst::Choice(options, filters))
+    .parse(input)
+}
+
+// `red`, `3:red` or `{color} hat`
+fn choice_option(input: Span) -> ParserResult<Rule> {
+    pair(
+        float
+            .verify(|weight: &f32| weight.is_finite() && *weight > 0.0)
+            .terminated(tag(":"))
+            .opt(),
+        nested_rule(']'),
+    )
+    .map(|(weight, rule)| Rule {
+        weight: weight.unwrap_or(1.0),
+        ..rule
+    })
+    .parse(input)
+}
+
+/**
+ * Text that's only sometimes included, either by chance (`{50%: a scar}`) or
+ * depending on a variable (`{if $race == Elf: pointy ears|else: round ears}`).
+ */
+fn rule_conditional(input: Span) -> ParserResult<RuleInst> {
+    tuple((
+        // like rule lines, one space after the `:` is part of the syntax
+        condition.terminated(tag(":")).terminated(tag(" ").opt()),
+        nested_rule('}'),
+        nested_rule('}')
+            .preceded_by(tag("|else:").terminated(tag(" ").opt()))
+            .opt(),
+    ))
+    .preceded_by(tag("{"))
+    .terminated(tag("}"))
+    .context("Invalid conditional, expected e.g. `{50%: text}` or `{if $name == value: text}`")
+    .map(|(condition, then, otherwise)| RuleInst::Conditional(condition, then, otherwise))
+    .parse(input)
+}
+
+// `50%`, `if $race == Elf` or `if $race != Elf`
+fn condition(input: Span) -> ParserResult<Condition> {
+    let chance = float
+        .terminated(tag("%"))
+        .verify(|chance: &f32| (0.0..=100.0).contains(chance))
+        .map(Condition::Chance);
+
+    let comparison = tuple((
+        variable.terminated(space0),
+        tag("==").or(tag("!=")).terminated(space0),
+        take_till1(|c| c == ':' || c == '{' || c == '}' || c == '|').map(trim_end),
+    ))
+    .map(|(name, operator, value)| {
+        let (name, value) = (name.to_string(), value.to_string());
+
+        match *operator.fragment() {
+            "==" => Condition::Equals(name, value),
+            _ => Condition::NotEquals(name, value),
+        }
+    })
+    .context("Invalid condition, expected e.g. `if $name == value` or `if $name != value`");
+
+    chance
+        .or(comparison.cut().preceded_by(tag("if").terminated(space1)))
+        .parse(input)
+}
+
+/**
+ * The parts of a rule nested in braces, e.g. an option of an inline choice,
+ * which end at a `|` or at `end`.
+ */
+fn nested_rule<'a>(end: char) -> impl FnMut(Span<'a>) -> ParserResult<'a, Rule> {
+    move |input| {
+        many0(
+            rule_dice_roll
+                .or(nested_literal(end))
+                .or(imported_rule_interpolation)
+                .or(rule_variable)
+                .or(rule_conditional)
+                .or(rule_choice)
+                .or(rule_interpolation),
+        )
+        .with_recognized()
+        .map(|(raw, parts)| Rule {
+            raw: raw.to_string(),
+            weight: 1.0,
+            range: None,
+            parts,
+        })
+        .parse(input)
+    }
+}
+
+// like `rule_literal`, but stopping at the end of the nested rule
+fn nested_literal<'a>(end: char) -> impl FnMut(Span<'a>) -> ParserResult<'a, RuleInst> {
+    move |input| {
+        fold_many1(
+            escape_sequence.map(String::from).or(take_till1(|c| {
+                c == '{' || c == '\\' || c == '|' || c == end
+            })
+            .map(|s: Span| s.to_string())),
+            String::new,
+            |mut acc, s| {
+                acc.push_str(&s);
+                acc
+            },
+        )
+        .map(RuleInst::Literal)
+        .parse(input)
+    }
+}
+
+// `{$hero}` or `{$hero|capitalize}`
+fn rule_variable(input: Span) -> ParserResult<RuleInst> {
+    pair(variable, filters)
+        .preceded_by(tag("{"))
+        .terminated(tag("}"))
+        .context("Invalid variable reference")
+        .map(|(name, filters)| RuleInst::Variable(name.to_string(), filters))
+        .parse(input)
+}
+
+// `>$hero`, storing the result of an interpolation in a variable
+fn binding(input: Span) -> ParserResult<Span> {
+    variable
+        .cut()
+        .preceded_by(tag(">"))
+        .context("Invalid variable binding, expected e.g. `>$hero`")
+        .parse(input)
+}
+
+// `$hero`
+fn variable(input: Span) -> ParserResult<Span> {
+    ident.preceded_by(tag("$")).parse(input)
+}
+
+fn bound(part: RuleInst, binding: Option<Span>) -> RuleInst {
+    match binding {
+        Some(name) => RuleInst::Bind(Box::new(part), name.to_string()),
+        None => part,
+    }
+}
+
 fn pipeline(input: Span) -> ParserResult<(Span, Vec<FilterOp>)> {
     pair(ident.cut(), filters)
         .context("Invalid interpolation pipeline")
         .parse(input)
 }
 
+/**
+ * A pipeline of filters, applied in order. Filters before `unique(N)`,
+ * `repeat(N)` or `join` transform each result, filters after `join`
+ * transform the joined text, e.g. `{x|plural|unique(3)|join(', ')|capitalize}`.
+ */
 fn filters(input: Span) -> ParserResult<Vec<FilterOp>> {
-    tuple((
-        many0(transform_filter.preceded_by(tag("|"))),
-        opt(unique_filter.preceded_by(tag("|"))),
-        opt(join_filter.preceded_by(tag("|"))),
-    ))
-    .map(|(transforms, unique, join)| {
-        let mut filters = transforms;
-        if let Some(u) = unique {
-            filters.push(u);
-        }
-        if let Some(j) = join {
-            filters.push(j);
+    let (rest, parsed) = many0(
+        unique_filter
+            .or(repeat_filter)
+            .or(join_filter)
+            .or(transform_filter)
+            .with_recognized()
+            .preceded_by(tag("|")),
+    )
+    .parse(input)?;
+
+    let mut count: Option<Span> = None;
+    let mut join: Option<Span> = None;
+
+    for (span, filter) in parsed.iter() {
+        match (filter, count, join) {
+            (FilterOp::Join(..), _, Some(_)) => {
+                return syntax_error(*span, "Only one `join` filter can be used");
+            }
+            (FilterOp::Join(..), _, None) => join = Some(*span),
+            (FilterOp::Unique(_) | FilterOp::Repeat(_), _, Some(join)) => {
+                return syntax_error(
+                    *span,
+                    format!(
+                        "`{}` must come before `{}`, which combines the results into one",
+                        span, join
+                    ),
+                );
+            }
+            (FilterOp::Unique(_) | FilterOp::Repeat(_), Some(_), None) => {
+                return syntax_error(*span, "Only one `unique` or `repeat` filter can be used");
+            }
+            (FilterOp::Unique(_) | FilterOp::Repeat(_), None, None) => count = Some(*span),
+            (_, Some(count), None) => {
+                return syntax_error(
+                    *span,
+                    format!(
+                        "`{}` after `{}` would apply to several results, move it before `{}` to transform each result or add a `join` before it to transform the joined text",
+                        span, count, count
+                    ),
+                );
+            }
+            _ => {}
         }
-        filters
-    })
-    .parse(input)
+    }
+
+    Ok((rest, parsed.into_iter().map(|(_, filter)| filter).collect()))
 }
 
-// `definite` or `indefinite` or `capitalize`
+// a filter applied to each result, e.g. `capitalize`, `count(3)` or a custom
+// filter registered for these definitions
 fn transform_filter(input: Span) -> ParserResult<FilterOp> {
-    tag("definite")
-        .or(tag("indefinite"))
-        .or(tag("capitalize"))
-        .map_res(|s: Span| s.parse())
-        .context("Invalid filter")
+    let (rest, filter) = recognize(pair(
+        alpha1,
+        take_while(|c: char| c.is_alphanumeric() || c == '_'),
+    ))
+    .map_res(|s: Span| s.parse())
+    .or(count_filter)
+    .context("Invalid filter")
+    .parse(input)?;
+
+    match filter {
+        FilterOp::Custom(name) if !custom_filter::is_parse_filter(&name) => {
+            syntax_error(input, format!("Unknown filter `{}`", name))
+        }
+        filter => Ok((rest, filter)),
+    }
+}
+
+// `count(3)` or `count($n)`
+fn count_filter(input: Span) -> ParserResult<FilterOp> {
+    recognize(pair(opt(char('-')), digit1))
+        .map_res(|s: Span| s.parse().map(Quantity::Number))
+        .or(variable.map(|name| Quantity::Variable(name.to_string())))
+        .cut()
+        .preceded_by(tag("count("))
+        .terminated(tag(")").cut())
+        .map(FilterOp::Count)
+        .context("Invalid count filter, expected a number or a variable, e.g. `count($n)`")
         .parse(input)
 }
 
-// `unique(3)`
+// `unique(3)` or `unique(1d4)`
 fn unique_filter(input: Span) -> ParserResult<FilterOp> {
-    digit1
+    let (rest, expr) = dice_expr
         .preceded_by(tag("unique("))
         .terminated(tag(")"))
         .context("Invalid unique filter")
-        .map_res(|s: Span| s.parse::<usize>())
-        .map_res(|n| {
-            if n <= 1 {
-                Err(ErrorTree::from_error_kind(
-                    "Idk what to write here",
-                    nom::error::ErrorKind::Digit,
-                ))
-            } else {
-                Ok(FilterOp::Unique(n))
-            }
-        })
-        .context("Unique must be passed a number greater than 1")
-        .parse(input)
+        .verify(|expr: &DiceExpr| expr.has_dice() || expr.bounds().is_some_and(|(_, n)| n > 1))
+        .context("Unique must be passed a number greater than 1 or a dice roll")
+        .parse(input)?;
+
+    check_count_limit(input, &expr)?;
+
+    Ok((rest, FilterOp::Unique(expr)))
+}
+
+// `repeat(3)` or `repeat(1d4)`, like `unique` but allowing duplicates
+fn repeat_filter(input: Span) -> ParserResult<FilterOp> {
+    let (rest, expr) = dice_expr
+        .preceded_by(tag("repeat("))
+        .terminated(tag(")"))
+        .context("Invalid repeat filter, expected a number or a dice roll, e.g. `repeat(1d4)`")
+        .parse(input)?;
+
+    check_count_limit(input, &expr)?;
+
+    Ok((rest, FilterOp::Repeat(expr)))
+}
+
+// counts that always exceed `COUNT_LIMIT`, dice that only might are checked
+// when rolled
+fn check_count_limit<'a>(
+    location: Span<'a>,
+    expr: &DiceExpr,
+) -> Result<(), nom::Err<ErrorTree<Span<'a>>>> {
+    match expr.bounds() {
+        Some((lowest, _)) if lowest > COUNT_LIMIT as i64 => syntax_error(
+            location,
+            format!(
+                "`{}` is too many results, at most {} can be generated at once",
+                expr, COUNT_LIMIT
+            ),
+        )
+        .map(|_: (Span, ())| ()),
+        _ => Ok(()),
+    }
 }
 
 // `join(', ')` or `join(', ', ' or ')`
@@ -332,16 +1335,24 @@ fn join_filter(input: Span) -> ParserResult<FilterOp> {
         .preceded_by(tag("join("))
         .terminated(tag(")"))
         .context("Invalid join filter")
-        .map(|(separator, conjunction): (Span, Option<Span>)| {
-            FilterOp::Join(separator.to_string(), conjunction.map(|s| s.to_string()))
-        })
+        .map(|(separator, conjunction)| FilterOp::Join(separator, conjunction))
         .parse(input)
 }
 
-fn str_like(input: Span) -> ParserResult<Span> {
-    take_while(|c: char| c != '\'')
-        .delimited_by(char::<Span, ErrorTree<Span>>('\''))
-        .parse(input)
+// `'text'`, where the text may contain escape sequences (e.g. `\'`)
+fn str_like(input: Span) -> ParserResult<String> {
+    fold_many0(
+        escape_sequence
+            .map(String::from)
+            .or(take_till1(|c| c == '\'' || c == '\\').map(|s: Span| s.to_string())),
+        String::new,
+        |mut acc, s| {
+            acc.push_str(&s);
+            acc
+        },
+    )
+    .delimited_by(char::<Span, ErrorTree<Span>>('\''))
+    .parse(input)
 }
 
 fn literal(input: Span) -> ParserResult<Span> {
@@ -386,67 +1397,430 @@ fn namespace_ident(input: Span) -> ParserResult<Span> {
     .parse(input)
 }
 
-#[cfg(test)]
-mod tests {
-    use rand::distributions::WeightedIndex;
+#[cfg(test)]
+mod tests {
+    use rand::distributions::WeightedIndex;
+
+    use super::*;
+    use crate::custom_filter::CustomFilters;
+    use crate::diagnostic::Diagnostic;
+
+    // test must have 1 new line between tables
+
+    #[test]
+    fn test_frontmatter() {
+        let table_definitions = parse_tables(
+            "---
+id: color
+title: Colors
+---
+1: Redish Orange
+3: Greenish Blue
+1: Purplish Pink
+3: Brownish Grey
+
+---
+id: shape
+title: Shapes
+---
+1: Circle
+1: Square
+1: Triangle
+1: Rectangle
+1: Pentagon
+1: Hexagon
+1: Octagon"
+                .into(),
+        )
+        .expect("Failed to parse");
+
+        assert_eq!(table_definitions.len(), 2, "Expected 2 table definitions");
+
+        if let [a, b] = table_definitions.as_slice() {
+            assert_eq!(a.id, "color");
+            assert_eq!(a.title, "Colors");
+            assert_eq!(a.namespace, None);
+            assert!(!a.export);
+            assert_eq!(a.weights, vec![1.0, 3.0, 1.0, 3.0]);
+            assert_eq!(
+                a.distribution,
+                WeightedIndex::new(vec![1.0, 3.0, 1.0, 3.0]).unwrap()
+            );
+
+            assert_eq!(b.id, "shape");
+            assert_eq!(b.title, "Shapes");
+            assert_eq!(b.namespace, None);
+            assert!(!b.export);
+            assert_eq!(b.weights, vec![1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0]);
+            assert_eq!(
+                b.distribution,
+                WeightedIndex::new(vec![1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0]).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn frontmatter_any_order_with_metadata_test() {
+        let result: Result<Frontmatter, ErrorTree<Span>> = final_parser(frontmatter)(
+            "---
+author: Jane Doe
+title: Colors
+export: true
+id: color
+---
+"
+            .into(),
+        );
+
+        assert!(result.is_ok());
+        let frontmatter = result.unwrap();
+
+        assert_eq!(*frontmatter.id.fragment(), "color");
+        assert_eq!(frontmatter.title, "Colors");
+        assert!(frontmatter.export);
+        assert_eq!(
+            frontmatter.metadata,
+            BTreeMap::from([("author".to_string(), "Jane Doe".to_string())])
+        );
+    }
+
+    #[test]
+    fn frontmatter_duplicate_key_test() {
+        let input = "---
+id: color
+title: Colors
+id: colour
+---
+";
+        let result: Result<Frontmatter, ErrorTree<Span>> = final_parser(frontmatter)(input.into());
+
+        if let Err(ErrorTree::Base { location, kind }) = result {
+            assert_eq!(location.location_line(), 4);
+            assert_eq!(location.location_offset(), input.rfind("id:").unwrap());
+            assert_eq!(*location.fragment(), "id");
+            assert_eq!(
+                kind.to_string(),
+                "external error:\n  Duplicate table attribute `id`"
+            );
+        } else {
+            panic!("Expected a duplicate key error");
+        }
+    }
+
+    #[test]
+    fn frontmatter_missing_key_test() {
+        let result: Result<Frontmatter, ErrorTree<Span>> = final_parser(frontmatter)(
+            "---
+id: color
+---
+"
+            .into(),
+        );
+
+        if let Err(ErrorTree::Base { location, kind }) = result {
+            assert_eq!(location.location_line(), 1);
+            assert!(kind
+                .to_string()
+                .contains("Missing required table attribute `title`"));
+        } else {
+            panic!("Expected a missing key error");
+        }
+    }
+
+    #[test]
+    fn frontmatter_mode_test() {
+        let result: Result<Frontmatter, ErrorTree<Span>> =
+            final_parser(frontmatter)("---\nid: tarot\ntitle: Tarot\nmode: deck\n---\n".into());
+
+        assert_eq!(result.unwrap().mode, TableMode::Deck);
+
+        for invalid in [
+            "---\nid: tarot\ntitle: Tarot\nmode: shuffled\n---\n",
+            "---\nid: tarot\ntitle: Tarot\nmode: deck\nroll: d6\n---\n",
+        ] {
+            let result: Result<Frontmatter, ErrorTree<Span>> =
+                final_parser(frontmatter)(invalid.into());
+
+            assert!(result.is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn frontmatter_cooldown_test() {
+        let result: Result<Frontmatter, ErrorTree<Span>> =
+            final_parser(frontmatter)("---\nid: loot\ntitle: Loot\ncooldown: 3\n---\n".into());
+
+        assert_eq!(result.unwrap().cooldown, 3);
+
+        for invalid in [
+            "---\nid: loot\ntitle: Loot\ncooldown: some\n---\n",
+            "---\nid: loot\ntitle: Loot\ncooldown: 500\n---\n",
+            "---\nid: loot\ntitle: Loot\ncooldown: 2\nroll: d6\n---\n",
+            "---\nid: loot\ntitle: Loot\nmode: deck\ncooldown: 2\n---\n",
+        ] {
+            let result: Result<Frontmatter, ErrorTree<Span>> =
+                final_parser(frontmatter)(invalid.into());
+
+            assert!(result.is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn frontmatter_inflections_test() {
+        let result: Result<Frontmatter, ErrorTree<Span>> = final_parser(frontmatter)(
+            "---
+id: monster
+title: Monsters
+plurals: Cyclops/Cyclopes, ox/oxen
+articles: an Herb, a one
+---
+"
+            .into(),
+        );
+
+        let inflections = result.unwrap().inflections;
+
+        assert_eq!(
+            inflections.plurals,
+            BTreeMap::from([
+                ("cyclops".to_string(), "cyclopes".to_string()),
+                ("ox".to_string(), "oxen".to_string())
+            ])
+        );
+        assert_eq!(
+            inflections.articles,
+            BTreeMap::from([
+                ("herb".to_string(), "an".to_string()),
+                ("one".to_string(), "a".to_string())
+            ])
+        );
+
+        let result: Result<Frontmatter, ErrorTree<Span>> = final_parser(frontmatter)(
+            "---
+id: monster
+title: Monsters
+plurals: cyclops
+---
+"
+            .into(),
+        );
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn frontmatter_invalid_export_test() {
+        let result: Result<Frontmatter, ErrorTree<Span>> = final_parser(frontmatter)(
+            "---
+id: color
+title: Colors
+export: yes
+---
+"
+            .into(),
+        );
 
-    use super::*;
+        assert!(result.is_err());
+    }
 
-    // test id cannot come after title
-    // test must have 1 new line between tables
+    #[test]
+    fn ranged_rules_test() {
+        let tables = parse_tables(
+            "---
+id: encounter
+title: Encounters
+roll: d100
+---
+01-05: Goblin
+06-95: Orc
+96-00: Dragon
+
+---
+id: reaction
+title: Reaction
+roll: 2d6
+---
+2-5: Hostile
+6-8: Neutral
+9-11: Friendly
+12: Helpful"
+                .into(),
+        )
+        .unwrap();
+
+        let encounter = &tables[0];
+        assert!(encounter.roll.is_some());
+        assert_eq!(encounter.rules[0].range, Some((1, 5)));
+        assert_eq!(encounter.rules[2].range, Some((96, 100)));
+        assert_eq!(encounter.rules[1].weight, 0.9);
+
+        let reaction = &tables[1];
+        assert_eq!(reaction.rules[3].range, Some((12, 12)));
+        assert_eq!(reaction.rules[3].weight, 1.0 / 36.0);
+    }
 
     #[test]
-    fn test_frontmatter() {
+    fn ranged_rules_errors_test() {
+        let cases = [
+            (
+                "roll: d6\n---\n1-3: a\n3-6: b",
+                "`3-6` overlaps the row on line 6",
+            ),
+            ("roll: d6\n---\n1-2: a\n5-6: b", "No row for rolls of 3-4 on `1d6`"),
+            (
+                "roll: 2d6\n---\n1-12: a",
+                "`1-12` is outside the possible rolls of `2d6` (2-12)",
+            ),
+            (
+                "roll: 1d4*10\n---\n10-40: a\n15: b",
+                "`15` can never be rolled on `1d4*10`",
+            ),
+            ("roll: d6\n---\n4-1: a", "Range `4-1` ends before it starts"),
+            (
+                "roll: d6\n---\n1.5: a",
+                "Rows must be a roll (`7`) or a range of rolls (`01-05`) when the table has a `roll`",
+            ),
+            (
+                "roll: d6!\n---\n1-6: a",
+                "`roll` must be a dice roll with a highest possible result, e.g. `d100` or `2d6`",
+            ),
+            (
+                "roll: d4294967295\n---\n1-4294967295: a",
+                "`1d4294967295` has too many possible results to work out the chance of each row",
+            ),
+            (
+                "roll: 10d20kh3\n---\n3-60: a",
+                "`10d20kh3` has too many possible results to work out the chance of each row",
+            ),
+            (
+                "roll: d6\n---\n5-6: b\n1-3: a\n2: c\n",
+                "`2` overlaps the row on line 7",
+            ),
+            (
+                "roll: 1d4*10\n---\n10: a\n40: b",
+                "No row for rolls of 20, 30 on `1d4*10`",
+            ),
+            (
+                "kind: any\n---\n1-6: a",
+                "Ranges of rolls require a `roll` table attribute, e.g. `roll: d100`",
+            ),
+        ];
+
+        for (source, message) in cases {
+            let source = format!("---\nid: t\ntitle: T\n{}", source);
+            let result = parse_tables(source.as_str().into());
+            let diagnostic = Diagnostic::from_error_tree(&source, &result.unwrap_err());
+
+            assert_eq!(diagnostic.message, message);
+        }
+    }
+
+    #[test]
+    fn comments_test() {
         let table_definitions = parse_tables(
-            "---
+            "# Colors used by the shape tables
+---
 id: color
+# the title is shown in the UI
 title: Colors
 ---
-1: Redish Orange
-3: Greenish Blue
-1: Purplish Pink
-3: Brownish Grey
+1: Red ## rare
+// blues
+3: Blue
+1: {shade|unique(2)|join(' ## ')}
+1: Room #5 // see http://example.com
 
+// shapes
 ---
 id: shape
 title: Shapes
 ---
 1: Circle
-1: Square
-1: Triangle
-1: Rectangle
-1: Pentagon
-1: Hexagon
-1: Octagon"
+1: #1 Square
+
+# end of file"
                 .into(),
         )
         .expect("Failed to parse");
 
-        assert_eq!(table_definitions.len(), 2, "Expected 2 table definitions");
+        if let [color, shape] = table_definitions.as_slice() {
+            assert_eq!(color.weights, vec![1.0, 3.0, 1.0, 1.0]);
+            assert_eq!(color.rules[0].raw, "Red");
+            assert_eq!(color.rules[2].raw, "{shade|unique(2)|join(' ## ')}");
+            // a lone `#` or `//` is rule text
+            assert_eq!(color.rules[3].raw, "Room #5 // see http://example.com");
+
+            let comments: Vec<(&str, u32, usize, bool)> = color
+                .comments
+                .iter()
+                .map(|c| (c.text.as_str(), c.line, c.column, c.trailing))
+                .collect();
 
-        if let [a, b] = table_definitions.as_slice() {
-            assert_eq!(a.id, "color");
-            assert_eq!(a.title, "Colors");
-            assert_eq!(a.namespace, None);
-            assert!(!a.export);
-            assert_eq!(a.weights, vec![1.0, 3.0, 1.0, 3.0]);
             assert_eq!(
-                a.distribution,
-                WeightedIndex::new(vec![1.0, 3.0, 1.0, 3.0]).unwrap()
+                comments,
+                vec![
+                    ("# Colors used by the shape tables", 1, 1, false),
+                    ("# the title is shown in the UI", 4, 1, false),
+                    ("## rare", 7, 8, true),
+                    ("// blues", 8, 1, false),
+                ]
             );
 
-            assert_eq!(b.id, "shape");
-            assert_eq!(b.title, "Shapes");
-            assert_eq!(b.namespace, None);
-            assert!(!b.export);
-            assert_eq!(b.weights, vec![1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0]);
+            assert_eq!(shape.rules[1].raw, "#1 Square");
             assert_eq!(
-                b.distribution,
-                WeightedIndex::new(vec![1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0]).unwrap()
+                shape
+                    .comments
+                    .iter()
+                    .map(|c| c.text.as_str())
+                    .collect::<Vec<_>>(),
+                vec!["// shapes", "# end of file"]
             );
+        } else {
+            panic!("Expected 2 table definitions");
         }
     }
 
+    #[test]
+    fn parse_tables_recovering_test() {
+        let (tables, errors) = parse_tables_recovering(
+            "---
+id: color
+title: Colors
+---
+1: Red
+x: Blue
+
+---
+id: shape
+title: Shapes
+---
+1: Circle
+
+---
+id: size
+title: Sizes
+---
+1: {tiny|}
+
+@@PRAGMA namespace=@hello/there
+---
+id: mood
+title: Moods
+---
+1: Happy
+
+# the end"
+                .into(),
+        );
+
+        assert_eq!(errors.len(), 2, "Expected 2 broken tables");
+        assert_eq!(tables.last().unwrap().comments[0].text, "# the end");
+        assert_eq!(
+            tables.iter().map(|t| t.id.as_str()).collect::<Vec<_>>(),
+            vec!["shape", "@hello/there/mood"]
+        );
+    }
+
     #[test]
     fn namespace_pragma_test() {
         let result: Result<Span, ErrorTree<Span>> =
@@ -540,9 +1914,188 @@ title: Shapes
         assert_eq!(&rule.parts.len(), &1);
         assert!(matches!(&rule.parts[0], RuleInst::DiceRoll(..)));
 
-        if let RuleInst::DiceRoll(dice_count, dice_size) = &rule.parts[0] {
-            assert_eq!(*dice_count, 3);
-            assert_eq!(*dice_size, 6);
+        if let RuleInst::DiceRoll(DiceExpr::Dice(dice), _) = &rule.parts[0] {
+            assert_eq!(dice.count, 3);
+            assert_eq!(dice.sides, 6);
+        }
+    }
+
+    #[test]
+    fn dice_expr_test() {
+        let cases = [
+            ("d20", "1d20"),
+            ("2d6+3", "2d6+3"),
+            ("1d4*10", "1d4*10"),
+            ("4d6kh3", "4d6kh3"),
+            ("2d20kl1", "2d20kl1"),
+            ("4d6dl1", "4d6dl1"),
+            ("3d6!", "3d6!"),
+            ("1d8+1d6-2", "1d8+1d6-2"),
+            ("1d8 + 1d6 - 2", "1d8+1d6-2"),
+            ("2d6+3*2", "2d6+3*2"),
+            ("(2d6+3)*2", "(2d6+3)*2"),
+            ("10-(1d4-1)", "10-(1d4-1)"),
+        ];
+
+        for (input, expected) in cases {
+            let result: Result<DiceExpr, ErrorTree<Span>> = final_parser(dice_expr)(input.into());
+
+            assert_eq!(result.unwrap().to_string(), expected, "parsing {}", input);
+        }
+
+        let result: Result<DiceExpr, ErrorTree<Span>> = final_parser(dice_expr)("1d8+1d6-2".into());
+
+        assert_eq!(
+            result.unwrap(),
+            DiceExpr::BinaryOp(
+                Operator::Subtract,
+                Box::new(DiceExpr::BinaryOp(
+                    Operator::Add,
+                    Box::new(DiceExpr::Dice(Dice {
+                        count: 1,
+                        sides: 8,
+                        explode: false,
+                        keep: None
+                    })),
+                    Box::new(DiceExpr::Dice(Dice {
+                        count: 1,
+                        sides: 6,
+                        explode: false,
+                        keep: None
+                    })),
+                )),
+                Box::new(DiceExpr::Number(2)),
+            )
+        );
+    }
+
+    #[test]
+    fn rule_line_dice_expr_test() {
+        let result: Result<Rule, ErrorTree<Span>> =
+            final_parser(rule_line)("1: {4d6kh3} strength, {3} {d0} {d6-table}".into());
+
+        let rule = result.unwrap();
+
+        assert!(matches!(&rule.parts[0], RuleInst::DiceRoll(..)));
+        assert!(matches!(&rule.parts[1], RuleInst::Literal(..)));
+        // not dice rolls, `{3}` doesn't roll any dice and dice need sides
+        assert!(matches!(&rule.parts[2], RuleInst::Interpolation(..)));
+        assert!(matches!(&rule.parts[4], RuleInst::Interpolation(..)));
+        assert!(matches!(&rule.parts[6], RuleInst::Interpolation(..)));
+    }
+
+    #[test]
+    fn rule_line_dice_limits_test() {
+        for (input, message) in [
+            ("1: {3000000000d6}", "Too many dice"),
+            ("1: {1001d6}", "Too many dice"),
+            ("1: {1d4294967296}", "Too many sides"),
+            ("1: {1d99999999999999999999}", "Too many sides"),
+        ] {
+            let result: Result<Rule, ErrorTree<Span>> = final_parser(rule_line)(input.into());
+
+            let error = format!("{:?}", result.expect_err(input));
+
+            assert!(error.contains(message), "{}: {}", input, error);
+        }
+
+        let result: Result<Rule, ErrorTree<Span>> =
+            final_parser(rule_line)("1: {1000d6} {1d4294967295}".into());
+        let rule = result.unwrap();
+
+        assert!(matches!(&rule.parts[0], RuleInst::DiceRoll(..)));
+        assert!(matches!(&rule.parts[2], RuleInst::DiceRoll(..)));
+    }
+
+    #[test]
+    fn rule_line_variables_test() {
+        let result: Result<Rule, ErrorTree<Span>> = final_parser(rule_line)(
+            "1: {first-name|capitalize>$hero} the {class}. {$hero|definite} has a pet".into(),
+        );
+
+        let rule = result.unwrap();
+
+        assert!(matches!(
+            &rule.parts[0],
+            RuleInst::Bind(part, name)
+                if name == "hero" && matches!(part.as_ref(), RuleInst::Interpolation(id, _) if id == "first-name")
+        ));
+        assert!(matches!(
+            &rule.parts[4],
+            RuleInst::Variable(name, filters) if name == "hero" && filters.len() == 1
+        ));
+
+        let result: Result<Rule, ErrorTree<Span>> =
+            final_parser(rule_line)("1: {first-name>hero}".into());
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn rule_line_choice_test() {
+        let result: Result<Rule, ErrorTree<Span>> = final_parser(rule_line)(
+            r"1: a {[3:red|1:light {color}|\]|]|unique(2)|join(', ')} hat".into(),
+        );
+
+        let rule = result.unwrap();
+
+        if let RuleInst::Choice(options, filters) = &rule.parts[1] {
+            let raw: Vec<&str> = options.iter().map(|o| o.raw.as_str()).collect();
+            let weights: Vec<f32> = options.iter().map(|o| o.weight).collect();
+
+            assert_eq!(raw, vec!["red", "light {color}", r"\]", ""]);
+            assert_eq!(weights, vec![3.0, 1.0, 1.0, 1.0]);
+            assert!(matches!(&options[0].parts[..], [RuleInst::Literal(s)] if s == "red"));
+            assert!(
+                matches!(&options[1].parts[1], RuleInst::Interpolation(id, _) if id == "color")
+            );
+            assert!(matches!(&options[2].parts[..], [RuleInst::Literal(s)] if s == "]"));
+            assert_eq!(filters.len(), 2);
+        } else {
+            panic!("Unexpected part: {:?}", rule.parts[1]);
+        }
+
+        let result: Result<Rule, ErrorTree<Span>> =
+            final_parser(rule_line)("1: {[red|blue}".into());
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn rule_line_conditional_test() {
+        let result: Result<Rule, ErrorTree<Span>> = final_parser(rule_line)(
+            "1: {name>$hero}{12.5%: , scarred}{if $race == Wood Elf: with {[pointy|long]} ears|else: bald}"
+                .into(),
+        );
+
+        let rule = result.unwrap();
+
+        assert!(matches!(
+            &rule.parts[1],
+            RuleInst::Conditional(Condition::Chance(chance), then, None)
+                if *chance == 12.5 && then.raw == ", scarred"
+        ));
+
+        if let RuleInst::Conditional(condition, then, Some(otherwise)) = &rule.parts[2] {
+            assert_eq!(
+                condition,
+                &Condition::Equals("race".to_string(), "Wood Elf".to_string())
+            );
+            assert_eq!(then.raw, "with {[pointy|long]} ears");
+            assert!(matches!(then.parts[1], RuleInst::Choice(..)));
+            assert_eq!(otherwise.raw, "bald");
+        } else {
+            panic!("Unexpected part: {:?}", rule.parts[2]);
+        }
+
+        for invalid in [
+            "1: {150%: never}",
+            "1: {if $race = Elf: x}",
+            "1: {if $race == Elf: x|y}",
+        ] {
+            let result: Result<Rule, ErrorTree<Span>> = final_parser(rule_line)(invalid.into());
+
+            assert!(result.is_err(), "{}", invalid);
         }
     }
 
@@ -613,6 +2166,69 @@ title: Shapes
         }
     }
 
+    #[test]
+    fn rule_line_inflection_filters_test() {
+        let result: Result<Rule, ErrorTree<Span>> = final_parser(rule_line)(
+            "1: {table|plural|singular|count(1)|count($n)|capitalize}".into(),
+        );
+
+        let rule = result.unwrap();
+
+        if let RuleInst::Interpolation(_table_id, filters) = &rule.parts[0] {
+            assert_eq!(
+                filters,
+                &vec![
+                    FilterOp::Plural,
+                    FilterOp::Singular,
+                    FilterOp::Count(Quantity::Number(1)),
+                    FilterOp::Count(Quantity::Variable("n".to_string())),
+                    FilterOp::Capitalize,
+                ]
+            );
+        } else {
+            panic!("Unexpected part: {:?}", rule.parts[0]);
+        }
+
+        let result: Result<Rule, ErrorTree<Span>> =
+            final_parser(rule_line)("1: {table|count(n)}".into());
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn rule_line_text_filters_test() {
+        let result: Result<Rule, ErrorTree<Span>> = final_parser(rule_line)(
+            "1: {1d20|ordinal} {2d6|words|upper>$n} {name|title|possessive|quote}".into(),
+        );
+
+        let rule = result.unwrap();
+
+        if let RuleInst::DiceRoll(_expr, filters) = &rule.parts[0] {
+            assert_eq!(filters, &vec![FilterOp::Ordinal]);
+        } else {
+            panic!("Unexpected part: {:?}", rule.parts[0]);
+        }
+
+        if let RuleInst::Bind(part, variable) = &rule.parts[2] {
+            assert_eq!(variable, "n");
+            assert!(matches!(
+                part.as_ref(),
+                RuleInst::DiceRoll(_, filters) if filters == &vec![FilterOp::Words, FilterOp::Upper]
+            ));
+        } else {
+            panic!("Unexpected part: {:?}", rule.parts[2]);
+        }
+
+        if let RuleInst::Interpolation(_table_id, filters) = &rule.parts[4] {
+            assert_eq!(
+                filters,
+                &vec![FilterOp::Title, FilterOp::Possessive, FilterOp::Quote]
+            );
+        } else {
+            panic!("Unexpected part: {:?}", rule.parts[4]);
+        }
+    }
+
     #[test]
     fn rule_line_filters_unique_join_test() {
         let result: Result<Rule, ErrorTree<Span>> =
@@ -629,7 +2245,7 @@ title: Shapes
             assert_eq!(filters.len(), 2);
             assert!(matches!(
                 filters.as_slice(),
-                [FilterOp::Unique(3), FilterOp::Join(..)]
+                [FilterOp::Unique(DiceExpr::Number(3)), FilterOp::Join(..)]
             ));
 
             if let FilterOp::Join(sep, conj) = &filters[1] {
@@ -639,6 +2255,36 @@ title: Shapes
         }
     }
 
+    #[test]
+    fn rule_line_filters_repeat_test() {
+        let result: Result<Rule, ErrorTree<Span>> = final_parser(rule_line)(
+            "1: {weapon|repeat(1d4)|join(', ', ' and ')} and {gem|unique(1d4+1)}".into(),
+        );
+
+        let rule = result.unwrap();
+
+        assert!(matches!(
+            &rule.parts[0],
+            RuleInst::Interpolation(_, filters)
+                if matches!(filters.as_slice(), [FilterOp::Repeat(DiceExpr::Dice(_)), FilterOp::Join(..)])
+        ));
+        assert!(matches!(
+            &rule.parts[2],
+            RuleInst::Interpolation(_, filters)
+                if matches!(filters.as_slice(), [FilterOp::Unique(DiceExpr::BinaryOp(..))])
+        ));
+
+        for invalid in [
+            "1: {weapon|unique(1)}",
+            "1: {weapon|repeat(d)}",
+            "1: {weapon|repeat(2)|unique(2)}",
+        ] {
+            let result: Result<Rule, ErrorTree<Span>> = final_parser(rule_line)(invalid.into());
+
+            assert!(result.is_err(), "{}", invalid);
+        }
+    }
+
     #[test]
     fn rule_line_filters_invalid_join_before_unique_test() {
         // invalid `unique(3)` after `join(', ')`
@@ -658,20 +2304,82 @@ title: Shapes
     }
 
     #[test]
-    fn rule_line_filters_invalid_capitalize_after_join_test() {
-        // invalid `capitalize` after `join(', ')`
+    fn rule_line_filters_after_join_test() {
         let result: Result<Rule, ErrorTree<Span>> =
-            final_parser(rule_line)("1: {table|join(', ')|capitalize}".into());
+            final_parser(rule_line)("1: {table|unique(3)|join(', ')|capitalize}".into());
 
-        assert!(result.is_err());
+        let rule = result.unwrap();
+
+        if let RuleInst::Interpolation(_table_id, filters) = &rule.parts[0] {
+            assert!(matches!(
+                filters.as_slice(),
+                [
+                    FilterOp::Unique(_),
+                    FilterOp::Join(..),
+                    FilterOp::Capitalize
+                ]
+            ));
+        } else {
+            panic!("Unexpected part: {:?}", rule.parts[0]);
+        }
+    }
+
+    #[test]
+    fn rule_line_filters_order_errors_test() {
+        let cases = [
+            (
+                "{table|unique(3)|capitalize}",
+                "`capitalize` after `unique(3)` would apply to several results, move it before `unique(3)` to transform each result or add a `join` before it to transform the joined text",
+            ),
+            (
+                "{table|join(', ')|repeat(2)}",
+                "`repeat(2)` must come before `join(', ')`, which combines the results into one",
+            ),
+            (
+                "{table|unique(2)|repeat(2)}",
+                "Only one `unique` or `repeat` filter can be used",
+            ),
+            (
+                "{[a|b]|join(', ')|upper|join(' ')}",
+                "Only one `join` filter can be used",
+            ),
+        ];
+
+        for (rule, message) in cases {
+            let source = format!("---\nid: t\ntitle: T\n---\n1: {}", rule);
+            let result = parse_tables(source.as_str().into());
+            let diagnostic = Diagnostic::from_error_tree(&source, &result.unwrap_err());
+
+            assert_eq!(diagnostic.message, message);
+        }
     }
 
     #[test]
     fn rule_line_filters_invalid_garbage_filter_test() {
+        let result: Result<Rule, ErrorTree<Span>> =
+            final_parser(rule_line)("1: literal {table|garbage!}".into());
+
+        assert!(result.is_err());
+
         let result: Result<Rule, ErrorTree<Span>> =
             final_parser(rule_line)("1: literal {table|garbage}".into());
 
         assert!(result.is_err());
+
+        // unless it's registered as a custom filter
+        let mut filters = CustomFilters::default();
+        filters
+            .register("garbage", |v: &str| v.to_string())
+            .unwrap();
+
+        let result: Result<Rule, ErrorTree<Span>> = custom_filter::parse_with(&filters, || {
+            final_parser(rule_line)("1: literal {table|garbage}".into())
+        });
+
+        assert!(matches!(
+            &result.unwrap().parts[1],
+            RuleInst::Interpolation(_, filters) if filters == &[FilterOp::Custom("garbage".to_string())]
+        ));
     }
 
     #[test]
@@ -723,6 +2431,66 @@ title: Shapes
         }
     }
 
+    #[test]
+    fn rule_line_escapes_test() {
+        let result: Result<Rule, ErrorTree<Span>> =
+            final_parser(rule_line)(r"1: a \{literal\} \| pipe\nand a \\ {table}".into());
+
+        assert!(result.is_ok());
+        let rule = result.unwrap();
+
+        assert_eq!(rule.parts.len(), 2);
+
+        if let RuleInst::Literal(lit) = &rule.parts[0] {
+            assert_eq!(lit.as_str(), "a {literal} | pipe\nand a \\ ");
+        } else {
+            panic!("Expected a literal");
+        }
+    }
+
+    #[test]
+    fn rule_line_unknown_escape_test() {
+        let result: Result<Rule, ErrorTree<Span>> =
+            final_parser(rule_line)(r"1: \o/ in C:\temp\".into());
+
+        if let [RuleInst::Literal(lit)] = result.unwrap().parts.as_slice() {
+            assert_eq!(lit.as_str(), r"\o/ in C:\temp\");
+        } else {
+            panic!("Expected a literal");
+        }
+    }
+
+    #[test]
+    fn count_limit_test() {
+        for input in ["unique(1001)", "repeat(100000000)", "repeat(1001+1d4)"] {
+            let result: Result<FilterOp, ErrorTree<Span>> =
+                final_parser(unique_filter.or(repeat_filter))(input.into());
+            let error = format!("{:?}", result.expect_err(input));
+
+            assert!(error.contains("too many results"), "{}: {}", input, error);
+        }
+
+        for input in ["unique(1000)", "repeat(1d1000000000)"] {
+            let result: Result<FilterOp, ErrorTree<Span>> =
+                final_parser(unique_filter.or(repeat_filter))(input.into());
+
+            assert!(result.is_ok(), "{}", input);
+        }
+    }
+
+    #[test]
+    fn join_filter_escaped_quote_test() {
+        let result: Result<FilterOp, ErrorTree<Span>> =
+            final_parser(join_filter)(r"join('\', ', ' \'n\' ')".into());
+
+        if let Ok(FilterOp::Join(separator, conjunction)) = result {
+            assert_eq!(separator, "', ");
+            assert_eq!(conjunction, Some(" 'n' ".to_string()));
+        } else {
+            panic!("Unexpected result: {:?}", result);
+        }
+    }
+
     #[test]
     fn filters_invalid_test() {
         // pipe with no filter keyword after
@@ -734,5 +2502,14 @@ title: Shapes
         let result: Result<Vec<FilterOp>, ErrorTree<Span>> = final_parser(filters)("|derp".into());
 
         assert!(result.is_err());
+
+        let result: Result<Vec<FilterOp>, ErrorTree<Span>> = final_parser(filters)("|2x".into());
+
+        assert!(result.is_err());
+
+        let result: Result<Vec<FilterOp>, ErrorTree<Span>> =
+            final_parser(filters)("|unique".into());
+
+        assert!(result.is_err());
     }
 }
@@ -0,0 +1,255 @@
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
+use wasm_bindgen::prelude::*;
+use web_sys::js_sys::Object;
+
+use crate::cooldown::RecentPicks;
+use crate::deck::DeckState;
+use crate::table_collection::{GenContext, TableCollection, TableError};
+use crate::trace::TracedResult;
+
+/**
+ * How many results a session remembers, oldest first out.
+ */
+static HISTORY_LIMIT: usize = 100;
+
+/**
+ * A run of generation over a `TableCollection` that can be saved and resumed
+ * exactly where it left off: the RNG carries on from the same point, the
+ * decks hold the same cards and cooldowns remember the same picks.
+ */
+#[wasm_bindgen]
+pub struct Session {
+    tables: TableCollection,
+    ctx: GenContext,
+    history: Vec<HistoryEntry>,
+}
+
+/**
+ * A result generated during a session.
+ */
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub table_id: String,
+    pub result: String,
+}
+
+/**
+ * Everything needed to resume a session, apart from the table definitions.
+ */
+#[derive(Serialize, Deserialize)]
+struct SessionState {
+    rng: ChaCha8Rng,
+    decks: DeckState,
+    // missing from sessions saved before cooldowns
+    #[serde(default)]
+    recent: RecentPicks,
+    history: Vec<HistoryEntry>,
+}
+
+#[wasm_bindgen]
+impl Session {
+    /**
+     * Starts a session over `table_definitions`, parsed with the custom
+     * `filters` as in `new TableCollection`. The session parses them itself
+     * rather than taking a collection, which JS would no longer be able to
+     * use.
+     */
+    #[wasm_bindgen(constructor)]
+    pub fn new_js(
+        table_definitions: &str,
+        seed: Option<u32>,
+        filters: Option<Object>,
+    ) -> Result<Session, JsError> {
+        let tables = TableCollection::new(table_definitions, filters)?;
+
+        Ok(Self::new(tables, seed.map(u64::from)))
+    }
+
+    #[wasm_bindgen(js_name = fromJson)]
+    pub fn from_json_js(
+        table_definitions: &str,
+        json: &str,
+        filters: Option<Object>,
+    ) -> Result<Session, JsError> {
+        let tables = TableCollection::new(table_definitions, filters)?;
+
+        Self::from_json(tables, json).map_err(|e| e.into())
+    }
+
+    #[wasm_bindgen(js_name = toJson)]
+    pub fn to_json_js(&self) -> Result<String, JsError> {
+        self.to_json().map_err(|e| e.into())
+    }
+
+    #[wasm_bindgen(js_name = gen)]
+    pub fn gen_js(&mut self, id: &str, cooldown: Option<usize>) -> Result<String, JsError> {
+        self.set_cooldown(cooldown);
+        self.gen(id).map_err(|e| e.into())
+    }
+
+    #[wasm_bindgen(js_name = genMany)]
+    pub fn gen_many_js(
+        &mut self,
+        id: &str,
+        count: usize,
+        cooldown: Option<usize>,
+    ) -> Result<Vec<String>, JsError> {
+        self.set_cooldown(cooldown);
+        self.gen_many(id, count).map_err(|e| e.into())
+    }
+
+    #[wasm_bindgen(js_name = genTraced)]
+    pub fn gen_traced_js(&mut self, id: &str, cooldown: Option<usize>) -> Result<JsValue, JsError> {
+        self.set_cooldown(cooldown);
+        Ok(serde_wasm_bindgen::to_value(&self.gen_traced(id)?)?)
+    }
+
+    #[wasm_bindgen(js_name = history)]
+    pub fn history_js(&self) -> Vec<JsValue> {
+        self.history
+            .iter()
+            .map(|h| serde_wasm_bindgen::to_value(h).unwrap())
+            .collect()
+    }
+
+    pub fn reshuffle(&self, id: &str) {
+        self.tables.reshuffle(id);
+    }
+
+    #[wasm_bindgen(js_name = remainingCards)]
+    pub fn remaining_cards_js(&self, id: &str) -> Result<Vec<String>, JsError> {
+        self.tables.remaining_cards_js(id)
+    }
+}
+
+impl Session {
+    pub fn new(tables: TableCollection, seed: Option<u64>) -> Session {
+        Self {
+            tables,
+            ctx: GenContext::new(seed),
+            history: vec![],
+        }
+    }
+
+    /**
+     * Resumes a session saved with `to_json` over `tables`, which should be
+     * the definitions it was saved with. See `TableCollection::restore_decks`
+     * for decks that changed since.
+     */
+    pub fn from_json(tables: TableCollection, json: &str) -> Result<Session, TableError> {
+        let state: SessionState = serde_json::from_str(json)
+            .map_err(|e| TableError::CallError(format!("Invalid session: {}", e)))?;
+
+        tables.restore_decks(state.decks);
+        tables.restore_recent_picks(state.recent);
+
+        let mut ctx = GenContext::new(None);
+        ctx.rng = state.rng;
+
+        Ok(Self {
+            tables,
+            ctx,
+            history: state.history,
+        })
+    }
+
+    pub fn to_json(&self) -> Result<String, TableError> {
+        let state = SessionState {
+            rng: self.ctx.rng.clone(),
+            decks: self.tables.deck_state(),
+            recent: self.tables.recent_picks(),
+            history: self.history.clone(),
+        };
+
+        serde_json::to_string(&state)
+            .map_err(|e| TableError::CallError(format!("Failed to save session: {}", e)))
+    }
+
+    pub fn tables(&self) -> &TableCollection {
+        &self.tables
+    }
+
+    pub fn history(&self) -> &[HistoryEntry] {
+        &self.history
+    }
+
+    /**
+     * Overrides the `cooldown` of the tables generated from here on, see
+     * `GenContext::cooldown`.
+     */
+    pub fn set_cooldown(&mut self, cooldown: Option<usize>) {
+        self.ctx.cooldown = cooldown;
+    }
+
+    pub fn gen(&mut self, id: &str) -> Result<String, TableError> {
+        let result = self.tables.gen_with(id, &mut self.ctx)?;
+        self.remember(id, &result);
+
+        Ok(result)
+    }
+
+    pub fn gen_many(&mut self, id: &str, count: usize) -> Result<Vec<String>, TableError> {
+        (0..count).map(|_| self.gen(id)).collect()
+    }
+
+    pub fn gen_traced(&mut self, id: &str) -> Result<TracedResult, TableError> {
+        let traced = self.tables.gen_traced_with(id, &mut self.ctx)?;
+        self.remember(id, &traced.result);
+
+        Ok(traced)
+    }
+
+    fn remember(&mut self, id: &str, result: &str) {
+        if self.history.len() == HISTORY_LIMIT {
+            self.history.remove(0);
+        }
+
+        self.history.push(HistoryEntry {
+            table_id: id.to_string(),
+            result: result.to_string(),
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static TABLES: &str = "---
+id: tarot
+title: Tarot
+mode: deck
+---
+1: The Fool
+1: The Tower
+1: The Star
+1: The Moon
+
+---
+id: weather
+title: Weather
+---
+1: {1d20} days of rain
+1: fog";
+
+    #[test]
+    fn test_resume_session() {
+        let mut session = Session::new(TableCollection::parse(TABLES).unwrap(), Some(7));
+        session.gen_many("tarot", 2).unwrap();
+        session.gen("weather").unwrap();
+
+        let json = session.to_json().unwrap();
+        let mut resumed =
+            Session::from_json(TableCollection::parse(TABLES).unwrap(), &json).unwrap();
+
+        assert_eq!(resumed.history(), session.history());
+
+        for id in ["tarot", "weather", "tarot", "weather"] {
+            assert_eq!(resumed.gen(id).unwrap(), session.gen(id).unwrap());
+        }
+
+        assert_eq!(resumed.to_json().unwrap(), session.to_json().unwrap());
+        assert!(Session::from_json(TableCollection::parse(TABLES).unwrap(), "{}").is_err());
+    }
+}
@@ -1,69 +1,164 @@
+use crate::analysis::{self, Enumeration, TableAnalysis};
+use crate::cooldown::{RecentPicks, RECENT_LIMIT};
+use crate::custom_filter::{self, CustomFilters, RESERVED_NAMES};
+use crate::deck::DeckState;
+use crate::diagnostic::{format_diagnostics, Diagnostic, Severity};
+use crate::dice::DiceExpr;
+use crate::inflection::{self, Inflections};
 use crate::nom_parser::{self, Span};
-use rand::distributions::{Uniform, WeightedIndex};
+use crate::text;
+use crate::trace::{TraceStep, TracedResult, Tracer};
+use rand::distributions::{WeightedError, WeightedIndex};
 use rand::prelude::*;
+use rand_chacha::ChaCha8Rng;
 use serde::Serialize;
-use std::collections::HashMap;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
 use std::str::FromStr;
 use thiserror::Error;
 use wasm_bindgen::prelude::*;
 use web_sys::console;
+use web_sys::js_sys::Object;
 
 static UNIQUE_GEN_LIMIT: usize = 20;
+/**
+ * How many results a `unique(N)` or `repeat(N)` filter can generate.
+ */
+pub static COUNT_LIMIT: usize = 1000;
+static DEFAULT_MAX_DEPTH: usize = 100;
 
 #[derive(Debug, Clone, Error)]
 pub enum TableError {
     #[error("Failed to parse: {0}")]
     ParseError(String),
+    #[error("Failed to parse: {}", format_diagnostics(.0))]
+    SyntaxError(Vec<Diagnostic>),
     #[error("Invalid definition: {0}")]
     InvalidDefinition(String),
     #[error("Invalid call: {0}")]
     CallError(String),
     #[error("Missing dependency: {0}")]
     MissingDependencyError(String, String),
+    #[error("Interpolation cycle: {}", .0.join(" -> "))]
+    CycleError(Vec<String>),
+    #[error("Exceeded the maximum interpolation depth of {0}")]
+    DepthLimitError(usize),
+    #[error("Unknown variable: ${0}")]
+    UnboundVariableError(String),
 }
 
 #[wasm_bindgen]
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct TableCollection {
     table_map: HashMap<String, TableDefinition>,
     external_identifiers: Vec<String>,
+    diagnostics: Vec<Diagnostic>,
+    // defaults to `DEFAULT_MAX_DEPTH`
+    max_depth: Option<usize>,
+    // drawn while generating, which otherwise doesn't change the collection
+    decks: RefCell<DeckState>,
+    // picked while generating, for cooldowns
+    recent: RefCell<RecentPicks>,
+    // looked up by name when a rule applies a filter that isn't built in
+    filters: CustomFilters,
 }
 
 #[wasm_bindgen]
 impl TableCollection {
+    /**
+     * Parses table definitions, with `filters` an optional object mapping the
+     * names of custom filters to functions taking and returning a string,
+     * e.g. `{ gold: (copper) => ... }` for `{price|gold}`.
+     */
     #[wasm_bindgen(constructor)]
-    pub fn new(table_definitions: &str) -> Result<TableCollection, JsError> {
-        let mut table_map = HashMap::new();
-        let tables = nom_parser::parse_tables(Span::new(table_definitions))
-            // @TODO: do something with `e`, better errors plz
-            .map_err(|_e| TableError::ParseError(table_definitions.to_string()))?;
+    pub fn new(
+        table_definitions: &str,
+        filters: Option<Object>,
+    ) -> Result<TableCollection, JsError> {
+        let filters = filters
+            .map(|f| CustomFilters::from_js(&f))
+            .transpose()?
+            .unwrap_or_default();
+
+        Self::parse_with_filters(table_definitions, filters).map_err(|e| e.into())
+    }
 
-        let mut external_identifiers: Vec<String> = vec![];
+    #[wasm_bindgen(js_name = parseRecovering)]
+    pub fn parse_recovering_js(
+        table_definitions: &str,
+        filters: Option<Object>,
+    ) -> Result<TableCollection, JsError> {
+        let filters = filters
+            .map(|f| CustomFilters::from_js(&f))
+            .transpose()?
+            .unwrap_or_default();
+
+        Ok(Self::parse_recovering_with_filters(
+            table_definitions,
+            filters,
+        ))
+    }
 
-        for table in tables {
-            if table.namespace.is_none() {
-                external_identifiers.extend(table.external_identifiers());
-            }
+    #[wasm_bindgen(js_name = comments)]
+    pub fn comments_js(&self) -> Vec<JsValue> {
+        self.comments()
+            .into_iter()
+            .map(|c| serde_wasm_bindgen::to_value(c).unwrap())
+            .collect()
+    }
 
-            table_map.insert(table.id.clone(), table);
-        }
+    #[wasm_bindgen(js_name = diagnostics)]
+    pub fn diagnostics_js(&self) -> Vec<JsValue> {
+        self.diagnostics
+            .iter()
+            .map(|d| serde_wasm_bindgen::to_value(d).unwrap())
+            .collect()
+    }
 
-        Ok(Self {
-            table_map,
-            external_identifiers,
-        })
+    /**
+     * Limits how deeply interpolations may nest while generating. Exceeding
+     * the limit fails generation with an error instead of overflowing the
+     * stack.
+     */
+    pub fn set_max_depth(&mut self, max_depth: usize) {
+        self.max_depth = Some(max_depth);
     }
 
     // @TODO: this should be able to say "here are the unresolved tables"
     pub fn validate_tables(&self) -> Result<Vec<String>, JsError> {
+        // resolving rules below draws from decks and picks rules, which
+        // validating shouldn't count
+        let decks = self.decks.borrow().clone();
+        let recent = self.recent.borrow().clone();
+        let result = self.validate_rules();
+        *self.decks.borrow_mut() = decks;
+        *self.recent.borrow_mut() = recent;
+
+        result
+    }
+
+    fn validate_rules(&self) -> Result<Vec<String>, JsError> {
         let mut missing_identifiers: Vec<String> = vec![];
 
+        if let Some(cycle) = self.find_cycles().into_iter().next() {
+            return Err(TableError::CycleError(cycle).into());
+        }
+
         for (table_id, table) in self.table_map.iter() {
             // only validate non-imported table definitions
             // @ASSUMPTION: the imported tables are valid
             if table.namespace.is_none() {
                 for rule in table.rules.iter() {
-                    if let Err(err) = rule.resolve(self) {
+                    if let Some(message) = self.unique_overflow(table, rule) {
+                        return Err(TableError::InvalidDefinition(format!(
+                            "in table \"{}\" for rule \"{}\". {}",
+                            table_id, rule.raw, message
+                        ))
+                        .into());
+                    }
+
+                    if let Err(err) = rule.resolve(self, &mut GenContext::new(None)) {
                         console::log_1(&format!("{:?}", err).into());
                         // only if the call error is missing table and the table is an external table?
                         // always return all external references?
@@ -71,10 +166,11 @@ impl TableCollection {
                             TableError::MissingDependencyError(_, id) => {
                                 missing_identifiers.push(id);
                             }
+                            // the variable is bound by a table interpolating
+                            // this one, unknown variables are reported when
+                            // parsing
+                            TableError::UnboundVariableError(_) => {}
                             // @TODO: ignore other failures?
-                            // This will cause `unique(N)` to fail if it can't generate a unique result
-                            // which is kind of unexpected when simply authoring a rule. It makes sense
-                            // when attempting to generate a result, but not when authoring.
                             e => {
                                 return Err(TableError::InvalidDefinition(format!(
                                     "in table \"{}\" for rule \"{}\". Original error: \"{}\"",
@@ -106,7 +202,12 @@ impl TableCollection {
         self.external_identifiers.clone()
     }
 
-    fn _gen(&self, id: &str, is_external: bool) -> Result<String, TableError> {
+    fn _gen(
+        &self,
+        id: &str,
+        is_external: bool,
+        ctx: &mut GenContext,
+    ) -> Result<String, TableError> {
         self.table_map
             .get(id)
             .ok_or(if is_external {
@@ -117,30 +218,608 @@ impl TableCollection {
             } else {
                 TableError::CallError(format!("No table found with id {}", id))
             })
-            .and_then(|table| table.gen(self))
+            .and_then(|table| self.gen_table(ctx, |ctx| table.gen(self, ctx)))
+    }
+
+    /**
+     * Generates a result from a table with `gen`, enforcing the depth limit
+     * and starting a new result at the top level. Every way of picking a
+     * table's rule goes through here.
+     */
+    fn gen_table(
+        &self,
+        ctx: &mut GenContext,
+        gen: impl FnOnce(&mut GenContext) -> Result<String, TableError>,
+    ) -> Result<String, TableError> {
+        let max_depth = self.max_depth.unwrap_or(DEFAULT_MAX_DEPTH);
+
+        if ctx.depth >= max_depth {
+            return Err(TableError::DepthLimitError(max_depth));
+        }
+
+        // variables are bound for the duration of a single result
+        if ctx.depth == 0 {
+            ctx.bindings.clear();
+        }
+
+        ctx.depth += 1;
+        let result = gen(ctx);
+        ctx.depth -= 1;
+
+        result
+    }
+
+    /**
+     * Generates a result from the table with the given `id`. Passing a `seed`
+     * makes the result reproducible, on any platform. It's a `u32` so JS can
+     * pass a plain number rather than a `BigInt`. Passing a `cooldown`
+     * overrides the table's, see `GenContext::cooldown`.
+     */
+    pub fn gen(
+        &self,
+        id: &str,
+        is_external: bool,
+        seed: Option<u32>,
+        cooldown: Option<usize>,
+    ) -> Result<String, JsError> {
+        self._gen(
+            id,
+            is_external,
+            &mut GenContext::new(seed.map(u64::from)).with_cooldown(cooldown),
+        )
+        .map_err(|e| e.into())
+    }
+
+    pub fn gen_many(
+        &self,
+        id: &str,
+        count: usize,
+        seed: Option<u32>,
+        cooldown: Option<usize>,
+    ) -> Result<Vec<String>, JsError> {
+        self.gen_many_with(
+            id,
+            count,
+            &mut GenContext::new(seed.map(u64::from)).with_cooldown(cooldown),
+        )
+        .map_err(|e| e.into())
+    }
+
+    /**
+     * Generates a result like `gen`, returning a `TracedResult` with the tree
+     * of table rolls, dice and filters that produced it.
+     */
+    pub fn gen_traced(
+        &self,
+        id: &str,
+        seed: Option<u32>,
+        cooldown: Option<usize>,
+    ) -> Result<JsValue, JsError> {
+        let traced = self.gen_traced_with(
+            id,
+            &mut GenContext::new(seed.map(u64::from)).with_cooldown(cooldown),
+        )?;
+
+        Ok(serde_wasm_bindgen::to_value(&traced)?)
+    }
+
+    #[wasm_bindgen(js_name = enumerate)]
+    pub fn enumerate_js(&self, id: &str) -> Result<JsValue, JsError> {
+        Ok(serde_wasm_bindgen::to_value(&self.enumerate(id)?)?)
+    }
+
+    #[wasm_bindgen(js_name = analyze)]
+    pub fn analyze_js(&self, id: &str) -> Result<JsValue, JsError> {
+        Ok(serde_wasm_bindgen::to_value(&self.analyze(id)?)?)
+    }
+
+    /**
+     * Puts every card drawn from the deck of table `id` back.
+     */
+    pub fn reshuffle(&self, id: &str) {
+        self.decks.borrow_mut().reshuffle(id);
+    }
+
+    #[wasm_bindgen(js_name = reshuffleAll)]
+    pub fn reshuffle_all(&self) {
+        *self.decks.borrow_mut() = DeckState::default();
+    }
+
+    /**
+     * The raw text of the cards left in the deck of table `id`.
+     */
+    #[wasm_bindgen(js_name = remainingCards)]
+    pub fn remaining_cards_js(&self, id: &str) -> Result<Vec<String>, JsError> {
+        Ok(self
+            .remaining_cards(id)?
+            .into_iter()
+            .map(|rule| rule.raw.clone())
+            .collect())
+    }
+
+    /**
+     * The decks as a plain object (not a `Map`), so that it survives
+     * `JSON.stringify` for `restoreDecks`.
+     */
+    #[wasm_bindgen(js_name = deckState)]
+    pub fn deck_state_js(&self) -> Result<JsValue, JsError> {
+        let serializer = serde_wasm_bindgen::Serializer::json_compatible();
+
+        Ok(self.deck_state().serialize(&serializer)?)
+    }
+
+    #[wasm_bindgen(js_name = restoreDecks)]
+    pub fn restore_decks_js(&self, state: JsValue) -> Result<(), JsError> {
+        self.restore_decks(serde_wasm_bindgen::from_value(state)?);
+        Ok(())
+    }
+}
+
+impl TableCollection {
+    /**
+     * Parses table definitions into a collection. On failure, the error holds
+     * a `Diagnostic` pointing at the offending text.
+     */
+    pub fn parse(table_definitions: &str) -> Result<TableCollection, TableError> {
+        Self::parse_with_filters(table_definitions, CustomFilters::default())
+    }
+
+    /**
+     * Parses table definitions whose rules may use the custom `filters`.
+     */
+    pub fn parse_with_filters(
+        table_definitions: &str,
+        filters: CustomFilters,
+    ) -> Result<TableCollection, TableError> {
+        let tables = custom_filter::parse_with(&filters, || {
+            nom_parser::parse_tables(Span::new(table_definitions))
+        })
+        .map_err(|e| {
+            TableError::SyntaxError(vec![Diagnostic::from_error_tree(table_definitions, &e)])
+        })?;
+
+        let mut collection = Self::from_tables(tables, vec![]);
+        collection.filters = filters;
+        let diagnostics = collection.validation_diagnostics(table_definitions);
+
+        if diagnostics.iter().any(|d| d.severity == Severity::Error) {
+            return Err(TableError::SyntaxError(diagnostics));
+        }
+
+        // warnings are kept for `diagnostics()`
+        collection.diagnostics = diagnostics;
+
+        Ok(collection)
+    }
+
+    /**
+     * Parses table definitions, recovering from errors in individual tables.
+     * The collection contains every valid table and a diagnostic for each
+     * table that could not be parsed.
+     */
+    pub fn parse_recovering(table_definitions: &str) -> TableCollection {
+        Self::parse_recovering_with_filters(table_definitions, CustomFilters::default())
+    }
+
+    pub fn parse_recovering_with_filters(
+        table_definitions: &str,
+        filters: CustomFilters,
+    ) -> TableCollection {
+        let (tables, errors) = custom_filter::parse_with(&filters, || {
+            nom_parser::parse_tables_recovering(Span::new(table_definitions))
+        });
+
+        let diagnostics = errors
+            .iter()
+            .map(|e| Diagnostic::from_error_tree(table_definitions, e))
+            .collect();
+
+        let mut collection = Self::from_tables(tables, diagnostics);
+        collection.filters = filters;
+
+        let invalid = collection.validation_diagnostics(table_definitions);
+
+        collection.diagnostics.extend(invalid);
+        collection.diagnostics.sort_by_key(|d| d.offset);
+        collection
+    }
+
+    pub fn diagnostics_for(table_definitions: &str) -> Vec<Diagnostic> {
+        Self::parse_recovering(table_definitions).diagnostics
+    }
+
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+
+    /**
+     * All comments in the parsed source, in source order.
+     */
+    pub fn comments(&self) -> Vec<&Comment> {
+        let mut comments: Vec<&Comment> = self
+            .table_map
+            .values()
+            .flat_map(|table| table.comments.iter())
+            .collect();
+
+        comments.sort_by_key(|c| c.offset);
+        comments
+    }
+
+    /**
+     * Finds tables that interpolate each other unconditionally, i.e. where
+     * every rule leads back into the cycle so generation can never finish.
+     * Each cycle is returned as a path of table ids that starts and ends with
+     * the same table.
+     */
+    pub fn find_cycles(&self) -> Vec<Vec<String>> {
+        // tables with at least one rule that can be resolved without a cycle
+        let mut finite: HashSet<&str> = HashSet::new();
+
+        loop {
+            let before = finite.len();
+
+            for (id, table) in self.table_map.iter() {
+                let is_finite = table.rules.iter().any(|rule| {
+                    rule.weight > 0.0
+                        && rule.interpolated_ids().all(|target| {
+                            finite.contains(target) || !self.table_map.contains_key(target)
+                        })
+                });
+
+                if is_finite {
+                    finite.insert(id);
+                }
+            }
+
+            if finite.len() == before {
+                break;
+            }
+        }
+
+        let mut infinite: Vec<&String> = self
+            .table_map
+            .keys()
+            .filter(|id| !finite.contains(id.as_str()))
+            .collect();
+        infinite.sort();
+
+        let mut cycles = vec![];
+        let mut visited: HashSet<&str> = HashSet::new();
+
+        // follow interpolations from each table until one repeats. Every
+        // table on the way has no finite rules, so there's always a next one
+        for start in infinite {
+            let mut path: Vec<&str> = vec![];
+            let mut current = start.as_str();
+
+            while !visited.contains(current) {
+                visited.insert(current);
+                path.push(current);
+
+                current = self.table_map[current]
+                    .rules
+                    .iter()
+                    .filter(|rule| rule.weight > 0.0)
+                    .flat_map(|rule| rule.interpolated_ids())
+                    .find(|target| self.table_map.contains_key(*target) && !finite.contains(target))
+                    .expect("an infinite table interpolates another infinite table");
+            }
+
+            if let Some(position) = path.iter().position(|id| *id == current) {
+                let mut cycle: Vec<String> =
+                    path[position..].iter().map(|id| id.to_string()).collect();
+                cycle.push(current.to_string());
+                cycles.push(cycle);
+            }
+        }
+
+        cycles
+    }
+
+    /**
+     * Problems spanning several tables, which can only be found once every
+     * table has been parsed.
+     */
+    fn validation_diagnostics(&self, table_definitions: &str) -> Vec<Diagnostic> {
+        let mut diagnostics = self.cycle_diagnostics(table_definitions);
+
+        diagnostics.extend(self.variable_diagnostics(table_definitions));
+        diagnostics.extend(self.unique_diagnostics(table_definitions));
+        diagnostics.sort_by_key(|d| d.offset);
+        diagnostics
+    }
+
+    /**
+     * Reports references to variables that no rule in the collection binds.
+     */
+    fn variable_diagnostics(&self, table_definitions: &str) -> Vec<Diagnostic> {
+        let bound: HashSet<&str> = self
+            .table_map
+            .values()
+            .flat_map(|table| table.rules.iter())
+            .flat_map(|rule| rule.bound_variables())
+            .collect();
+
+        let mut diagnostics = vec![];
+
+        for table in self.table_map.values() {
+            for rule in table.rules.iter() {
+                for name in rule.referenced_variables() {
+                    if bound.contains(name) {
+                        continue;
+                    }
+
+                    // also found in conditions, e.g. `{if $race == Elf: ...}`
+                    let reference = format!("${}", name);
+                    let offset = locate_in_rule(table_definitions, table, rule, &reference)
+                        .unwrap_or(table.offset);
+
+                    diagnostics.push(Diagnostic::at_token(
+                        Severity::Error,
+                        format!(
+                            "Unknown variable `${}`, bind one with e.g. `{{table>${}}}`",
+                            name, name
+                        ),
+                        table_definitions,
+                        offset,
+                    ));
+                }
+            }
+        }
+
+        diagnostics
+    }
+
+    /**
+     * Reports `unique(N)` filters that can ask for more distinct results than
+     * there are.
+     */
+    fn unique_diagnostics(&self, table_definitions: &str) -> Vec<Diagnostic> {
+        let mut diagnostics = vec![];
+
+        for table in self.table_map.values() {
+            for rule in table.rules.iter() {
+                if let Some(message) = self.unique_overflow(table, rule) {
+                    let offset = locate_in_rule(table_definitions, table, rule, "unique(")
+                        .unwrap_or(table.offset);
+
+                    diagnostics.push(Diagnostic::at_token(
+                        Severity::Error,
+                        message,
+                        table_definitions,
+                        offset,
+                    ));
+                }
+            }
+        }
+
+        diagnostics
+    }
+
+    /**
+     * Describes the first `unique(N)` in `rule` whose N can be more than the
+     * number of distinct results it draws from, e.g. `unique(1d6)` of a table
+     * with 4 rows.
+     */
+    fn unique_overflow(&self, table: &TableDefinition, rule: &Rule) -> Option<String> {
+        rule.nested_parts()
+            .into_iter()
+            .map(RuleInst::unbound)
+            .find_map(|part| {
+                let Some((expr, true)) = count_filter(part.filters()) else {
+                    return None;
+                };
+
+                let (_, most) = expr.bounds()?;
+                let distinct = analysis::distinct_results(self, table, part).ok()??;
+
+                (most > distinct as i64).then(|| {
+                    format!(
+                        "`unique({})` can ask for {} distinct results, but there are only {}",
+                        expr, most, distinct
+                    )
+                })
+            })
+    }
+
+    fn cycle_diagnostics(&self, table_definitions: &str) -> Vec<Diagnostic> {
+        self.find_cycles()
+            .into_iter()
+            .map(|cycle| {
+                Diagnostic::at_token(
+                    Severity::Error,
+                    format!(
+                        "Table `{}` can never finish generating, its rules always interpolate {}",
+                        cycle[0],
+                        cycle.join(" -> ")
+                    ),
+                    table_definitions,
+                    self.table_map[&cycle[0]].offset,
+                )
+            })
+            .collect()
+    }
+
+    /**
+     * Lists every distinct output of a table, expanding interpolations, dice
+     * and filters, along with an estimate of how many outputs there are.
+     */
+    pub fn enumerate(&self, id: &str) -> Result<Enumeration, TableError> {
+        analysis::enumerate_table(self, id)
+    }
+
+    pub(crate) fn table(&self, id: &str) -> Option<&TableDefinition> {
+        self.table_map.get(id)
+    }
+
+    /**
+     * Computes the exact probability of each rule of a table being selected
+     * and, for rules that don't interpolate other tables, of each output.
+     */
+    pub fn analyze(&self, id: &str) -> Result<TableAnalysis, TableError> {
+        self.table_map
+            .get(id)
+            .map(|table| analysis::analyze_table(table, &self.filters))
+            .ok_or(TableError::CallError(format!(
+                "No table found with id {}",
+                id
+            )))
+    }
+
+    pub fn custom_filters(&self) -> &CustomFilters {
+        &self.filters
+    }
+
+    /**
+     * The cards left in the deck of a `mode: deck` table.
+     */
+    pub fn remaining_cards(&self, id: &str) -> Result<Vec<&Rule>, TableError> {
+        let table = self.deck_table(id)?;
+
+        Ok(self
+            .decks
+            .borrow()
+            .remaining(table)
+            .into_iter()
+            .map(|i| &table.rules[i])
+            .collect())
+    }
+
+    pub fn deck_state(&self) -> DeckState {
+        self.decks.borrow().clone()
     }
 
-    pub fn gen(&self, id: &str, is_external: bool) -> Result<String, JsError> {
-        self._gen(id, is_external).map_err(|e| e.into())
+    /**
+     * Restores decks saved with `deck_state`. Decks of tables that are no
+     * longer decks, and cards that no longer exist, are left out.
+     */
+    pub fn restore_decks(&self, state: DeckState) {
+        let mut decks = DeckState::default();
+
+        for (id, drawn) in state.drawn {
+            let Ok(table) = self.deck_table(&id) else {
+                continue;
+            };
+
+            let mut cards: Vec<usize> = vec![];
+
+            for card in drawn {
+                if card < table.rules.len() && !cards.contains(&card) {
+                    cards.push(card);
+                }
+            }
+
+            decks.drawn.insert(id, cards);
+        }
+
+        *self.decks.borrow_mut() = decks;
     }
 
-    fn _gen_many(&self, id: &str, count: usize) -> Result<Vec<String>, JsError> {
+    fn deck_table(&self, id: &str) -> Result<&TableDefinition, TableError> {
         let table = self.table_map.get(id).ok_or(TableError::CallError(format!(
             "No table found with id {}",
             id
         )))?;
 
+        match table.mode {
+            TableMode::Deck => Ok(table),
+            TableMode::Random => Err(TableError::CallError(format!(
+                "Table {} isn't a deck, add `mode: deck` to its attributes",
+                id
+            ))),
+        }
+    }
+
+    fn draw_card(&self, table: &TableDefinition, rng: &mut impl Rng) -> usize {
+        self.decks.borrow_mut().draw(table, rng)
+    }
+
+    fn pick_rule(&self, table: &TableDefinition, cooldown: usize, rng: &mut impl Rng) -> usize {
+        self.recent.borrow_mut().pick(table, cooldown, rng)
+    }
+
+    pub fn recent_picks(&self) -> RecentPicks {
+        self.recent.borrow().clone()
+    }
+
+    /**
+     * Restores picks saved with `recent_picks`, leaving out tables and rules
+     * that no longer exist.
+     */
+    pub fn restore_recent_picks(&self, state: RecentPicks) {
+        let mut recent = RecentPicks::default();
+
+        for (id, picked) in state.picked {
+            let Some(table) = self.table_map.get(&id) else {
+                continue;
+            };
+
+            let picked = picked
+                .into_iter()
+                .filter(|i| *i < table.rules.len())
+                .collect();
+
+            recent.picked.insert(id, picked);
+        }
+
+        *self.recent.borrow_mut() = recent;
+    }
+
+    pub fn gen_with(&self, id: &str, ctx: &mut GenContext) -> Result<String, TableError> {
+        self._gen(id, false, ctx)
+    }
+
+    pub fn gen_traced_with(
+        &self,
+        id: &str,
+        ctx: &mut GenContext,
+    ) -> Result<TracedResult, TableError> {
+        let tracer = ctx.trace.replace(Tracer::default());
+        let result = self._gen(id, false, ctx);
+        let trace = ctx.trace.take().and_then(|mut t| t.take());
+
+        ctx.trace = tracer;
+
+        Ok(TracedResult {
+            result: result?,
+            trace: trace.expect("a successful roll is always traced"),
+        })
+    }
+
+    pub fn gen_many_with(
+        &self,
+        id: &str,
+        count: usize,
+        ctx: &mut GenContext,
+    ) -> Result<Vec<String>, TableError> {
         let mut results = Vec::with_capacity(count);
 
         for _ in 0..count {
-            results.push(table.gen(self)?);
+            results.push(self._gen(id, false, ctx)?);
         }
 
         Ok(results)
     }
 
-    pub fn gen_many(&self, id: &str, count: usize) -> Result<Vec<String>, JsError> {
-        self._gen_many(id, count)
+    fn from_tables(tables: Vec<TableDefinition>, diagnostics: Vec<Diagnostic>) -> Self {
+        let mut table_map = HashMap::new();
+        let mut external_identifiers: Vec<String> = vec![];
+
+        for table in tables {
+            if table.namespace.is_none() {
+                external_identifiers.extend(table.external_identifiers());
+            }
+
+            table_map.insert(table.id.clone(), table);
+        }
+
+        Self {
+            table_map,
+            external_identifiers,
+            diagnostics,
+            ..Default::default()
+        }
     }
 }
 
@@ -150,8 +829,22 @@ pub struct TableDefinition {
     pub namespace: Option<String>,
     pub title: String,
     pub export: bool,
+    // frontmatter attributes that aren't otherwise understood by the engine
+    pub metadata: BTreeMap<String, String>,
     #[allow(unused)]
     pub rules: Vec<Rule>,
+    pub comments: Vec<Comment>,
+    // byte offset of the `id` attribute in the source, for diagnostics
+    #[serde(skip)]
+    pub offset: usize,
+    // when set, rules are picked by rolling this and finding the rule with
+    // the matching range rather than by weight
+    pub roll: Option<DiceExpr>,
+    // exceptions to the English rules used by filters in this table's rules
+    pub inflections: Inflections,
+    pub mode: TableMode,
+    // how many of the table's latest picks can't be picked again
+    pub cooldown: usize,
     #[allow(unused)]
     pub weights: Vec<f32>,
     #[serde(skip)]
@@ -176,17 +869,151 @@ impl TableDefinition {
             namespace: namespace.clone(),
             title,
             export,
+            metadata: BTreeMap::new(),
             rules,
+            comments: vec![],
+            offset: 0,
+            roll: None,
+            inflections: Inflections::default(),
+            mode: TableMode::default(),
+            cooldown: 0,
             weights: weights.to_owned(),
             distribution: WeightedIndex::new(&weights).unwrap(),
         }
     }
 
-    pub fn gen(&self, tables: &TableCollection) -> Result<String, TableError> {
-        let mut rng = rand::thread_rng();
-        let rule = &self.rules[self.distribution.sample(&mut rng)];
+    pub fn with_metadata(mut self, metadata: BTreeMap<String, String>) -> Self {
+        self.metadata = metadata;
+        self
+    }
+
+    pub fn with_comments(mut self, comments: Vec<Comment>) -> Self {
+        self.comments = comments;
+        self
+    }
+
+    pub fn with_offset(mut self, offset: usize) -> Self {
+        self.offset = offset;
+        self
+    }
+
+    pub fn with_roll(mut self, roll: DiceExpr) -> Self {
+        self.roll = Some(roll);
+        self
+    }
+
+    pub fn with_inflections(mut self, inflections: Inflections) -> Self {
+        self.inflections = inflections;
+        self
+    }
+
+    pub fn with_mode(mut self, mode: TableMode) -> Self {
+        self.mode = mode;
+        self
+    }
 
-        rule.resolve(tables)
+    pub fn with_cooldown(mut self, cooldown: usize) -> Self {
+        self.cooldown = cooldown;
+        self
+    }
+
+    pub fn gen(
+        &self,
+        tables: &TableCollection,
+        ctx: &mut GenContext,
+    ) -> Result<String, TableError> {
+        let mut rolls = vec![];
+
+        let (index, rolled) = match &self.roll {
+            Some(roll) => {
+                let total = roll.roll(&mut ctx.rng, &mut rolls)?;
+                let index = self
+                    .rules
+                    .iter()
+                    .position(|rule| {
+                        rule.range
+                            .is_some_and(|(lo, hi)| lo <= total && total <= hi)
+                    })
+                    .ok_or(TableError::CallError(format!(
+                        "No rule in table {} for a roll of {}",
+                        self.id, total
+                    )))?;
+
+                (index, Some(total))
+            }
+            None => match self.mode {
+                TableMode::Random => {
+                    // a cooldown passed to the call only applies to the table
+                    // called, not the tables it interpolates
+                    let cooldown = match ctx.depth {
+                        1 => ctx.cooldown.unwrap_or(self.cooldown),
+                        _ => self.cooldown,
+                    };
+
+                    if cooldown > RECENT_LIMIT {
+                        return Err(TableError::CallError(format!(
+                            "A cooldown of {} is too long, at most the last {} picks are remembered",
+                            cooldown, RECENT_LIMIT
+                        )));
+                    }
+
+                    (tables.pick_rule(self, cooldown, &mut ctx.rng), None)
+                }
+                TableMode::Deck => (tables.draw_card(self, &mut ctx.rng), None),
+            },
+        };
+
+        let roll_step = match (&self.roll, rolled) {
+            (Some(roll), Some(total)) => Some(TraceStep::Dice {
+                expression: roll.to_string(),
+                rolls,
+                total,
+            }),
+            _ => None,
+        };
+
+        self.gen_rule(index, roll_step, tables, ctx)
+    }
+
+    /**
+     * Resolves the rule at `index`, recording `roll_step` (the roll that
+     * picked it, if any) in the trace.
+     */
+    fn gen_rule(
+        &self,
+        index: usize,
+        roll_step: Option<TraceStep>,
+        tables: &TableCollection,
+        ctx: &mut GenContext,
+    ) -> Result<String, TableError> {
+        let rule = &self.rules[index];
+
+        if let Some(trace) = ctx.trace.as_mut() {
+            trace.enter_table(&self.id, index, rule);
+
+            if let Some(step) = roll_step {
+                trace.record(step);
+            }
+        }
+
+        // filters in this table's rules use its inflections
+        let inflections = std::mem::replace(&mut ctx.inflections, self.inflections.clone());
+        let result = rule.resolve(tables, ctx);
+        ctx.inflections = inflections;
+
+        if let Some(trace) = ctx.trace.as_mut() {
+            trace.exit_table(result.as_deref().unwrap_or_default());
+        }
+
+        result
+    }
+
+    /**
+     * Whether every rule is plain text, so picking a different rule always
+     * gives a different result (barring duplicate rows).
+     */
+    pub fn is_plain_text(&self) -> bool {
+        self.roll.is_none() && self.rules.iter().all(Rule::is_plain_text)
     }
 
     pub fn external_identifiers(&self) -> Vec<String> {
@@ -197,165 +1024,597 @@ impl TableDefinition {
     }
 }
 
+/**
+ * How a table picks a rule: at random every time, or like drawing a card from
+ * a shuffled deck, where a rule isn't picked again until the deck runs out or
+ * is reshuffled.
+ */
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TableMode {
+    #[default]
+    Random,
+    Deck,
+}
+
+/**
+ * A `#` or `//` comment in the source. Comments are ignored when generating,
+ * but are kept with their position so tooling can reproduce the source.
+ */
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Comment {
+    // including the comment marker
+    pub text: String,
+    pub offset: usize,
+    pub line: u32,
+    pub column: usize,
+    // whether the comment follows a rule on the same line
+    pub trailing: bool,
+}
+
 #[derive(Debug, Clone, Serialize)]
 pub struct Rule {
     pub raw: String,
     pub weight: f32,
+    // the rolls that select this rule, for tables with a `roll`
+    pub range: Option<(i64, i64)>,
     pub parts: Vec<RuleInst>,
 }
 
 impl Rule {
-    pub fn resolve(&self, tables: &TableCollection) -> Result<String, TableError> {
+    pub fn resolve(
+        &self,
+        tables: &TableCollection,
+        ctx: &mut GenContext,
+    ) -> Result<String, TableError> {
         // keep track of context
         // forward pass to resolve all interpolations
         // backwards pass to resolve built-ins (e.g. article)
         let resolved: Result<Vec<String>, TableError> = self
             .parts
             .iter()
-            .map(|part| match part {
-                RuleInst::DiceRoll(count, sides) => Ok(roll_dice(*count, *sides).to_string()),
-                RuleInst::Literal(str) => Ok(str.to_string()),
-                RuleInst::Interpolation(id, filters) => {
-                    self.resolve_interpolation(id, filters, tables)
-                }
-                RuleInst::ExternalInterpolation(_ns, _id, nsid, filters) => {
-                    self.resolve_interpolation(nsid, filters, tables)
-                }
-            })
+            .map(|part| self.resolve_part(part, tables, ctx))
             .collect();
 
         Ok(resolved?.join(""))
     }
 
+    pub fn is_plain_text(&self) -> bool {
+        self.parts
+            .iter()
+            .all(|part| matches!(part, RuleInst::Literal(_)))
+    }
+
+    fn resolve_part(
+        &self,
+        part: &RuleInst,
+        tables: &TableCollection,
+        ctx: &mut GenContext,
+    ) -> Result<String, TableError> {
+        match part {
+            RuleInst::DiceRoll(expr, filters) => {
+                let mut rolls = vec![];
+                let total = expr.roll(&mut ctx.rng, &mut rolls)?;
+
+                if let Some(trace) = ctx.trace.as_mut() {
+                    trace.record(TraceStep::Dice {
+                        expression: expr.to_string(),
+                        rolls,
+                        total,
+                    });
+                }
+
+                let mut value = total.to_string();
+
+                for filter in filters {
+                    filter.apply(&mut value, &tables.filters, ctx)?;
+                }
+
+                Ok(value)
+            }
+            RuleInst::Literal(str) => Ok(str.to_string()),
+            RuleInst::Interpolation(id, filters) => {
+                self.resolve_interpolation(id, filters, tables, ctx)
+            }
+            RuleInst::ExternalInterpolation(_ns, _id, nsid, filters) => {
+                self.resolve_interpolation(nsid, filters, tables, ctx)
+            }
+            RuleInst::Choice(options, filters) => {
+                self.resolve_choice(options, filters, tables, ctx)
+            }
+            RuleInst::Conditional(condition, then, otherwise) => {
+                let passed = condition.check(ctx)?;
+
+                if let Some(trace) = ctx.trace.as_mut() {
+                    trace.record(TraceStep::Condition {
+                        condition: condition.to_string(),
+                        passed,
+                    });
+                }
+
+                match (passed, otherwise) {
+                    (true, _) => then.resolve(tables, ctx),
+                    (false, Some(otherwise)) => otherwise.resolve(tables, ctx),
+                    (false, None) => Ok(String::new()),
+                }
+            }
+            RuleInst::Bind(part, name) => {
+                let value = self.resolve_part(part, tables, ctx)?;
+
+                ctx.bindings.insert(name.clone(), value.clone());
+                Ok(value)
+            }
+            RuleInst::Variable(name, filters) => {
+                let mut value = ctx
+                    .bindings
+                    .get(name)
+                    .cloned()
+                    .ok_or(TableError::UnboundVariableError(name.clone()))?;
+
+                for filter in filters {
+                    filter.apply(&mut value, &tables.filters, ctx)?;
+                }
+
+                Ok(value)
+            }
+        }
+    }
+
     /**
-     * Resolves an interpolation rule by generating a result from the table. If
-     * the rule involves a `unique(N)` filter, it will attempt to generate N
-     * unique results and concatenate them according to a `join(S)` filter (or
-     * fall back to an empty string).
+     * Resolves an interpolation rule by generating a result from the table.
      */
     fn resolve_interpolation(
         &self,
         id: &str,
-        filters: &Vec<FilterOp>,
+        filters: &[FilterOp],
         tables: &TableCollection,
+        ctx: &mut GenContext,
     ) -> Result<String, TableError> {
-        // if `opts` contains a `FilterOp::unique(N)`, set count to N
-        let count = filters
-            .iter()
-            .find_map(|o| match o {
-                FilterOp::Unique(n) => Some(*n),
-                _ => None,
-            })
-            // if count is None, set to 1
-            .unwrap_or(1);
+        let step = TraceStep::Interpolation {
+            table_id: id.to_string(),
+            filters: filters.to_vec(),
+            rolls: vec![],
+            unique_retries: 0,
+            result: String::new(),
+        };
 
-        // if `opts` contains a `FilterOp::join(S)`, set separator to S
-        let (separator, conjunction) = filters
-            .iter()
-            .find_map(|o| match o {
-                FilterOp::Join(s, c) => Some((s.clone(), c.clone())),
-                _ => None,
-            })
-            // if separator is None, set to empty string
-            .unwrap_or(("".to_string(), None));
+        // decks already draw distinct rows, and tables with a cooldown have
+        // to pick (and record) every row like any other roll of the table
+        let plain_table = tables
+            .table(id)
+            .filter(|t| t.mode == TableMode::Random && t.cooldown == 0 && t.is_plain_text());
+
+        match (plain_table, count_filter(filters)) {
+            // draw distinct rows rather than retrying until they differ
+            (Some(table), Some((_, true))) => {
+                let mut sampler = RuleSampler::new(table.weights.clone(), true).map_err(|e| {
+                    TableError::CallError(format!("Invalid table {}: {}", table.id, e))
+                })?;
+
+                self.resolve_filtered(filters, tables, step, ctx, |ctx| {
+                    let index = sampler
+                        .sample(&mut ctx.rng)
+                        .ok_or_else(|| self.not_enough_results())?;
+
+                    tables.gen_table(ctx, |ctx| table.gen_rule(index, None, tables, ctx))
+                })
+            }
+            _ => {
+                self.resolve_filtered(filters, tables, step, ctx, |ctx| tables._gen(id, true, ctx))
+            }
+        }
+    }
+
+    /**
+     * Resolves an inline choice by picking one of its options by weight.
+     */
+    fn resolve_choice(
+        &self,
+        options: &[Rule],
+        filters: &[FilterOp],
+        tables: &TableCollection,
+        ctx: &mut GenContext,
+    ) -> Result<String, TableError> {
+        let weights = options.iter().map(|o| o.weight).collect();
+        let unique = matches!(count_filter(filters), Some((_, true)));
+        let mut sampler =
+            RuleSampler::new(weights, unique && options.iter().all(Rule::is_plain_text)).map_err(
+                |e| TableError::CallError(format!("Invalid choice in rule {}: {}", self.raw, e)),
+            )?;
+
+        let step = TraceStep::Choice {
+            filters: filters.to_vec(),
+            rolls: vec![],
+            unique_retries: 0,
+            result: String::new(),
+        };
+
+        self.resolve_filtered(filters, tables, step, ctx, |ctx| {
+            let index = sampler
+                .sample(&mut ctx.rng)
+                .ok_or_else(|| self.not_enough_results())?;
+            let option = &options[index];
+
+            if let Some(trace) = ctx.trace.as_mut() {
+                trace.enter_table("", index, option);
+            }
+
+            let result = option.resolve(tables, ctx);
+
+            if let Some(trace) = ctx.trace.as_mut() {
+                trace.exit_table(result.as_deref().unwrap_or_default());
+            }
+
+            result
+        })
+    }
+
+    /**
+     * Generates results with `gen` and applies filters to each, recording
+     * `step` in the trace. If the filters include `unique(N)` or `repeat(N)`,
+     * N is rolled and it will attempt to generate N (unique) results and
+     * concatenate them according to a `join(S)` filter (or fall back to an
+     * empty string). Filters after the `join` apply to the joined text.
+     *
+     * Duplicates are retried, giving up after a run of duplicates that grows
+     * with the number of distinct results found, since each new result is
+     * harder to find than the last.
+     */
+    fn resolve_filtered(
+        &self,
+        filters: &[FilterOp],
+        tables: &TableCollection,
+        step: TraceStep,
+        ctx: &mut GenContext,
+        mut gen: impl FnMut(&mut GenContext) -> Result<String, TableError>,
+    ) -> Result<String, TableError> {
+        let stages = FilterStages::new(filters);
+
+        // if `opts` contains a `FilterOp::unique(N)` or `FilterOp::repeat(N)`,
+        // roll N for the count, otherwise set it to 1
+        let (count, unique) = match stages.count {
+            Some((expr, unique)) => {
+                let mut rolls = vec![];
+                let total = expr.roll(&mut ctx.rng, &mut rolls)?;
+
+                // recorded before `step`, so rolls are attached to it
+                if let (Some(trace), true) = (ctx.trace.as_mut(), expr.has_dice()) {
+                    trace.record(TraceStep::Dice {
+                        expression: expr.to_string(),
+                        rolls,
+                        total,
+                    });
+                }
+
+                if total > COUNT_LIMIT as i64 {
+                    return Err(TableError::CallError(format!(
+                        "`{}` rolled {} in rule {}, but at most {} results can be generated at once",
+                        expr, total, self.raw, COUNT_LIMIT
+                    )));
+                }
+
+                (total.max(0) as usize, unique)
+            }
+            None => (1, true),
+        };
+
+        if let Some(trace) = ctx.trace.as_mut() {
+            trace.record(step);
+        }
 
         let mut results = vec![];
         let mut failed_attempts = 0;
+        // since the last distinct result
+        let mut duplicates = 0;
 
         while results.len() < count {
-            let mut result = tables._gen(id, true)?;
+            let mut result = gen(ctx)?;
 
-            for opt in filters {
-                opt.apply(&mut result);
+            for filter in stages.each {
+                filter.apply(&mut result, &tables.filters, ctx)?;
             }
 
-            if !results.contains(&result) {
+            if !unique || !results.contains(&result) {
                 results.push(result);
+                duplicates = 0;
             } else {
                 failed_attempts += 1;
+                duplicates += 1;
             }
 
-            if failed_attempts > UNIQUE_GEN_LIMIT {
+            if duplicates > UNIQUE_GEN_LIMIT * (results.len() + 1) {
                 return Err(TableError::CallError(format!(
-                    "Failed to generate unique result for rule {} after 20 attempts",
-                    self.raw
+                    "Failed to generate unique result for rule {} after {} attempts",
+                    self.raw, duplicates
                 )));
             }
         }
 
-        if conjunction.is_some() && results.len() > 1 {
-            let conjunction = conjunction.unwrap();
-            let last = results.pop().unwrap();
+        let (separator, conjunction) = stages.join;
+        let mut output = join_results(results, separator, conjunction);
+
+        for filter in stages.joined {
+            filter.apply(&mut output, &tables.filters, ctx)?;
+        }
+
+        if let Some(trace) = ctx.trace.as_mut() {
+            trace.exit_interpolation(failed_attempts, &output);
+        }
+
+        Ok(output)
+    }
+
+    fn not_enough_results(&self) -> TableError {
+        TableError::CallError(format!(
+            "Not enough distinct results for `unique` in rule {}",
+            self.raw
+        ))
+    }
+
+    /**
+     * The ids of every table this rule interpolates, namespaced for external
+     * tables. Tables only interpolated by an option of an inline choice are
+     * left out, since the rule doesn't always reach them.
+     */
+    pub fn interpolated_ids(&self) -> impl Iterator<Item = &str> {
+        self.parts
+            .iter()
+            .map(RuleInst::unbound)
+            .filter_map(|p| match p {
+                RuleInst::Interpolation(id, _) => Some(id.as_str()),
+                RuleInst::ExternalInterpolation(_ns, _id, nsid, _) => Some(nsid.as_str()),
+                _ => None,
+            })
+    }
+
+    /**
+     * The names of the variables this rule binds, e.g. `hero` for
+     * `{name>$hero}`.
+     */
+    pub fn bound_variables(&self) -> impl Iterator<Item = &str> {
+        self.nested_parts().into_iter().filter_map(|p| match p {
+            RuleInst::Bind(_, name) => Some(name.as_str()),
+            _ => None,
+        })
+    }
+
+    pub fn referenced_variables(&self) -> impl Iterator<Item = &str> {
+        self.nested_parts().into_iter().flat_map(|p| {
+            let variable = match p {
+                RuleInst::Variable(name, _) => Some(name.as_str()),
+                RuleInst::Conditional(condition, ..) => condition.variable(),
+                _ => None,
+            };
 
-            Ok(format!(
-                "{}{}{}",
-                results.join(separator.as_str()),
-                conjunction,
-                last
-            ))
-        } else {
-            Ok(results.join(separator.as_str()))
-        }
+            variable
+                .into_iter()
+                .chain(p.filters().iter().filter_map(FilterOp::variable))
+        })
     }
 
     pub fn external_identifiers(&self) -> Vec<String> {
-        self.parts
-            .iter()
+        self.nested_parts()
+            .into_iter()
             .filter_map(|p| match p {
                 RuleInst::ExternalInterpolation(ns, _id, _nsid, _vec) => Some(ns.to_string()),
                 _ => None,
             })
             .collect::<Vec<String>>()
     }
+
+    /**
+     * Every part of the rule, including bound parts and the parts of each
+     * option of an inline choice.
+     */
+    fn nested_parts(&self) -> Vec<&RuleInst> {
+        let mut parts = vec![];
+        let mut pending: Vec<&RuleInst> = self.parts.iter().rev().collect();
+
+        while let Some(part) = pending.pop() {
+            parts.push(part);
+
+            match part {
+                RuleInst::Bind(part, _) => pending.push(part),
+                RuleInst::Choice(options, _) => {
+                    pending.extend(options.iter().rev().flat_map(|o| o.parts.iter().rev()))
+                }
+                RuleInst::Conditional(_, then, otherwise) => pending.extend(
+                    otherwise
+                        .iter()
+                        .chain([then])
+                        .flat_map(|r| r.parts.iter().rev()),
+                ),
+                _ => {}
+            }
+        }
+
+        parts
+    }
 }
 
 #[derive(Debug, Clone, Serialize)]
 pub enum RuleInst {
-    DiceRoll(usize, usize), // (count, sides)
+    // (expression, filters)
+    DiceRoll(DiceExpr, Vec<FilterOp>),
     Literal(String),
     // (table id, filters)
     Interpolation(String, Vec<FilterOp>),
     // (namespace, table id, namespaced id, filters)
     ExternalInterpolation(String, String, String, Vec<FilterOp>),
+    // stores the result of another part in a variable, e.g. `{name>$hero}`
+    // (part, variable name)
+    Bind(Box<RuleInst>, String),
+    // (variable name, filters)
+    Variable(String, Vec<FilterOp>),
+    // picks one of several inline options, e.g. `{[3:red|1:blue]}`
+    // (options, filters)
+    Choice(Vec<Rule>, Vec<FilterOp>),
+    // text included only sometimes, e.g. `{50%: with a scar}`
+    // (condition, then, else)
+    Conditional(Condition, Rule, Option<Rule>),
 }
 
-#[derive(Debug, Clone, Serialize)]
+impl RuleInst {
+    /**
+     * The part itself, or the part whose result is bound for a `Bind`.
+     */
+    pub fn unbound(&self) -> &RuleInst {
+        match self {
+            RuleInst::Bind(part, _) => part.unbound(),
+            part => part,
+        }
+    }
+
+    pub fn filters(&self) -> &[FilterOp] {
+        match self {
+            RuleInst::DiceRoll(_, filters)
+            | RuleInst::Interpolation(_, filters)
+            | RuleInst::ExternalInterpolation(_, _, _, filters)
+            | RuleInst::Variable(_, filters)
+            | RuleInst::Choice(_, filters) => filters,
+            _ => &[],
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub enum Condition {
+    // (percent chance)
+    Chance(f32),
+    // (variable name, value)
+    Equals(String, String),
+    NotEquals(String, String),
+}
+
+impl Condition {
+    pub fn check(&self, ctx: &mut GenContext) -> Result<bool, TableError> {
+        match self {
+            Condition::Chance(chance) => Ok(ctx.rng.gen_bool(*chance as f64 / 100.0)),
+            Condition::Equals(name, value) | Condition::NotEquals(name, value) => {
+                let bound = ctx
+                    .bindings
+                    .get(name)
+                    .ok_or(TableError::UnboundVariableError(name.clone()))?;
+
+                Ok((bound == value) == matches!(self, Condition::Equals(..)))
+            }
+        }
+    }
+
+    /**
+     * The variable the condition compares, if any.
+     */
+    pub fn variable(&self) -> Option<&str> {
+        match self {
+            Condition::Chance(_) => None,
+            Condition::Equals(name, _) | Condition::NotEquals(name, _) => Some(name.as_str()),
+        }
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Condition::Chance(chance) => write!(f, "{}%", chance),
+            Condition::Equals(name, value) => write!(f, "if ${} == {}", name, value),
+            Condition::NotEquals(name, value) => write!(f, "if ${} != {}", name, value),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub enum FilterOp {
     DefiniteArticle,
     IndefiniteArticle,
     Capitalize,
+    Upper,
+    Lower,
+    Title,
+    Sentence,
+    Trim,
+    Possessive,
+    Quote,
+    // numeric filters, which leave anything but an integer as is
+    Ordinal,
+    Words,
+    Roman,
+    // (count)
+    Unique(DiceExpr),
+    // like `Unique`, but results may repeat
     // (count)
-    Unique(usize),
+    Repeat(DiceExpr),
+    Plural,
+    Singular,
+    // singular for a quantity of one, otherwise plural
+    Count(Quantity),
     // (separator, conjunction)
     Join(String, Option<String>),
+    // (name), looked up in the collection's `CustomFilters` when applied
+    Custom(String),
 }
 
 impl FilterOp {
-    pub fn apply(&self, value: &mut String) {
+    /**
+     * Whether the filter acts on the list of results rather than on each
+     * result, i.e. `unique(N)`, `repeat(N)` and `join`.
+     */
+    pub fn is_list_filter(&self) -> bool {
+        matches!(
+            self,
+            FilterOp::Unique(_) | FilterOp::Repeat(_) | FilterOp::Join(..)
+        )
+    }
+
+    pub fn apply(
+        &self,
+        value: &mut String,
+        custom: &CustomFilters,
+        ctx: &GenContext,
+    ) -> Result<(), TableError> {
         match self {
             FilterOp::DefiniteArticle => {
                 value.insert_str(0, "the ");
             }
-            FilterOp::IndefiniteArticle
-                if value.starts_with('a')
-                    || value.starts_with('e')
-                    || value.starts_with('i')
-                    || value.starts_with('o')
-                    || value.starts_with('u') =>
-            {
-                value.insert_str(0, "an ");
-            }
             FilterOp::IndefiniteArticle => {
-                value.insert_str(0, "a ");
-            }
-            FilterOp::Capitalize => {
-                let mut chars = value.chars();
-                if let Some(first) = chars.next() {
-                    *value = format!("{}{}", first.to_uppercase(), chars.as_str());
-                }
+                let article = inflection::indefinite_article(value, &ctx.inflections.articles);
+
+                value.insert_str(0, &format!("{} ", article));
             }
+            FilterOp::Capitalize => *value = text::capitalize(value),
+            FilterOp::Upper => *value = value.to_uppercase(),
+            FilterOp::Lower => *value = value.to_lowercase(),
+            FilterOp::Title => *value = text::title_case(value),
+            FilterOp::Sentence => *value = text::sentence_case(value),
+            FilterOp::Trim => *value = text::trim(value),
+            FilterOp::Possessive => *value = text::possessive(value),
+            FilterOp::Ordinal => *value = text::ordinal(value),
+            FilterOp::Words => *value = text::words(value),
+            FilterOp::Roman => *value = text::roman(value),
+            FilterOp::Quote => *value = format!("\"{}\"", value),
             FilterOp::Unique(_count) => {}
+            FilterOp::Repeat(_count) => {}
             FilterOp::Join(_separator, _conjunction) => {}
+            FilterOp::Plural => *value = inflection::pluralize(value, &ctx.inflections.plurals),
+            FilterOp::Singular => *value = inflection::singularize(value, &ctx.inflections.plurals),
+            FilterOp::Custom(name) => *value = custom.apply(name, value)?,
+            FilterOp::Count(quantity) => {
+                *value = match quantity.resolve(ctx)?.abs() {
+                    1 => inflection::singularize(value, &ctx.inflections.plurals),
+                    _ => inflection::pluralize(value, &ctx.inflections.plurals),
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /**
+     * The variable the filter reads, e.g. `n` for `count($n)`.
+     */
+    pub fn variable(&self) -> Option<&str> {
+        match self {
+            FilterOp::Count(Quantity::Variable(name)) => Some(name.as_str()),
+            _ => None,
         }
     }
 }
@@ -368,6 +1627,19 @@ impl FromStr for FilterOp {
             "definite" => Ok(FilterOp::DefiniteArticle),
             "indefinite" => Ok(FilterOp::IndefiniteArticle),
             "capitalize" => Ok(FilterOp::Capitalize),
+            "plural" => Ok(FilterOp::Plural),
+            "singular" => Ok(FilterOp::Singular),
+            "upper" => Ok(FilterOp::Upper),
+            "lower" => Ok(FilterOp::Lower),
+            "title" => Ok(FilterOp::Title),
+            "sentence" => Ok(FilterOp::Sentence),
+            "trim" => Ok(FilterOp::Trim),
+            "possessive" => Ok(FilterOp::Possessive),
+            "ordinal" => Ok(FilterOp::Ordinal),
+            "words" => Ok(FilterOp::Words),
+            "roman" => Ok(FilterOp::Roman),
+            "quote" => Ok(FilterOp::Quote),
+            _ if !RESERVED_NAMES.contains(&s) => Ok(FilterOp::Custom(s.to_string())),
             _ => Err(TableError::ParseError(format!(
                 "Invalid filter operation: {}",
                 s
@@ -376,15 +1648,187 @@ impl FromStr for FilterOp {
     }
 }
 
-pub fn roll_dice(count: usize, sides: usize) -> usize {
-    let mut rng = rand::thread_rng();
-    let mut total = 0;
+/**
+ * A number given to a filter, either directly or as a variable holding one.
+ */
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub enum Quantity {
+    Number(i64),
+    Variable(String),
+}
+
+impl Quantity {
+    pub fn resolve(&self, ctx: &GenContext) -> Result<i64, TableError> {
+        match self {
+            Quantity::Number(n) => Ok(*n),
+            Quantity::Variable(name) => {
+                let value = ctx
+                    .bindings
+                    .get(name)
+                    .ok_or(TableError::UnboundVariableError(name.clone()))?;
+
+                value.trim().parse().map_err(|_| {
+                    TableError::CallError(format!(
+                        "Expected `${}` to be a number, but it was \"{}\"",
+                        name, value
+                    ))
+                })
+            }
+        }
+    }
+}
+
+/**
+ * Mutable state for a single generation call, threaded through every table,
+ * rule and dice roll involved in producing a result.
+ *
+ * The RNG is seeded explicitly (rather than using `thread_rng`) so that the
+ * same seed and table definitions produce the same output on native and wasm.
+ */
+#[derive(Debug, Clone)]
+pub struct GenContext {
+    pub rng: ChaCha8Rng,
+    // only collected when a trace was requested
+    pub trace: Option<Tracer>,
+    // how many interpolations deep generation currently is
+    pub depth: usize,
+    // variables bound while generating the current result, e.g. `hero` for
+    // `{name>$hero}`
+    pub bindings: HashMap<String, String>,
+    // inflections of the table whose rule is being resolved
+    pub inflections: Inflections,
+    // overrides the `cooldown` of the table being generated: how many of its
+    // latest picks (in this collection) can't be picked again
+    pub cooldown: Option<usize>,
+}
+
+impl GenContext {
+    pub fn new(seed: Option<u64>) -> Self {
+        let rng = match seed {
+            Some(seed) => ChaCha8Rng::seed_from_u64(seed),
+            None => ChaCha8Rng::from_entropy(),
+        };
+
+        Self {
+            rng,
+            trace: None,
+            depth: 0,
+            bindings: HashMap::new(),
+            inflections: Inflections::default(),
+            cooldown: None,
+        }
+    }
+
+    pub fn with_cooldown(mut self, cooldown: Option<usize>) -> Self {
+        self.cooldown = cooldown;
+        self
+    }
+}
+
+/**
+ * The count of a `unique(N)` or `repeat(N)` filter, and whether the results
+ * must be distinct.
+ */
+pub fn count_filter(filters: &[FilterOp]) -> Option<(&DiceExpr, bool)> {
+    filters.iter().find_map(|f| match f {
+        FilterOp::Unique(n) => Some((n, true)),
+        FilterOp::Repeat(n) => Some((n, false)),
+        _ => None,
+    })
+}
+
+/**
+ * Picks rules by weight, optionally without replacement so that `unique(N)`
+ * never picks the same rule twice. Returns `None` once every rule has been
+ * picked.
+ */
+struct RuleSampler {
+    weights: Vec<f32>,
+    // `None` once every rule has been picked
+    distribution: Option<WeightedIndex<f32>>,
+    without_replacement: bool,
+}
+
+impl RuleSampler {
+    fn new(weights: Vec<f32>, without_replacement: bool) -> Result<Self, WeightedError> {
+        Ok(Self {
+            distribution: Some(WeightedIndex::new(&weights)?),
+            weights,
+            without_replacement,
+        })
+    }
+
+    fn sample(&mut self, rng: &mut impl Rng) -> Option<usize> {
+        let index = self.distribution.as_ref()?.sample(rng);
+
+        if self.without_replacement {
+            // rebuilt rather than updated, since updating accumulates
+            // rounding errors that could leave picked rules with a weight
+            self.weights[index] = 0.0;
+            self.distribution = WeightedIndex::new(&self.weights).ok();
+        }
+
+        Some(index)
+    }
+}
+
+/**
+ * The stages of a filter pipeline, in order: filters applied to each result,
+ * how many results to draw, how to join them and filters applied to the
+ * joined text. Assumes the order the parser enforces, see `nom_parser::filters`.
+ */
+pub struct FilterStages<'a> {
+    pub each: &'a [FilterOp],
+    // (count, whether results must be distinct)
+    pub count: Option<(&'a DiceExpr, bool)>,
+    // (separator, conjunction)
+    pub join: (&'a str, Option<&'a str>),
+    pub joined: &'a [FilterOp],
+}
+
+impl<'a> FilterStages<'a> {
+    pub fn new(filters: &'a [FilterOp]) -> Self {
+        let each_end = filters
+            .iter()
+            .position(FilterOp::is_list_filter)
+            .unwrap_or(filters.len());
+
+        let join = filters.iter().enumerate().find_map(|(i, f)| match f {
+            FilterOp::Join(s, c) => Some((i, s.as_str(), c.as_deref())),
+            _ => None,
+        });
 
-    for _ in 0..count {
-        total += rng.sample(Uniform::new(1, sides + 1));
+        let (join, joined) = match join {
+            Some((i, separator, conjunction)) => ((separator, conjunction), &filters[i + 1..]),
+            None => (("", None), &filters[filters.len()..]),
+        };
+
+        Self {
+            each: &filters[..each_end],
+            count: count_filter(filters),
+            join,
+            joined,
+        }
     }
+}
+
+/**
+ * Joins results with `separator`, using `conjunction` instead between the
+ * last two (e.g. "a, b and c").
+ */
+pub fn join_results(
+    mut results: Vec<String>,
+    separator: &str,
+    conjunction: Option<&str>,
+) -> String {
+    match conjunction {
+        Some(conjunction) if results.len() > 1 => {
+            let last = results.pop().unwrap();
 
-    total
+            format!("{}{}{}", results.join(separator), conjunction, last)
+        }
+        _ => results.join(separator),
+    }
 }
 
 pub fn format_namespaced_id(namespace: &Option<String>, id: String) -> String {
@@ -403,12 +1847,7 @@ pub fn namespaced_rules<'a>(namespace: &Option<String>, rules: &'a mut [Rule]) -
     if namespace.is_some() {
         for rule in rules.iter_mut() {
             for part in rule.parts.iter_mut() {
-                if let RuleInst::Interpolation(id, filters) = part {
-                    *part = RuleInst::Interpolation(
-                        format_namespaced_id(namespace, id.to_string()),
-                        filters.clone(),
-                    );
-                }
+                namespaced_part(namespace, part);
             }
         }
     }
@@ -416,23 +1855,831 @@ pub fn namespaced_rules<'a>(namespace: &Option<String>, rules: &'a mut [Rule]) -
     rules
 }
 
+fn namespaced_part(namespace: &Option<String>, part: &mut RuleInst) {
+    match part {
+        RuleInst::Interpolation(id, filters) => {
+            *part = RuleInst::Interpolation(
+                format_namespaced_id(namespace, id.to_string()),
+                filters.clone(),
+            );
+        }
+        RuleInst::Bind(part, _) => namespaced_part(namespace, part),
+        RuleInst::Choice(options, _) => {
+            for part in options.iter_mut().flat_map(|o| o.parts.iter_mut()) {
+                namespaced_part(namespace, part);
+            }
+        }
+        RuleInst::Conditional(_, then, otherwise) => {
+            for part in otherwise
+                .iter_mut()
+                .chain([then])
+                .flat_map(|r| r.parts.iter_mut())
+            {
+                namespaced_part(namespace, part);
+            }
+        }
+        _ => {}
+    }
+}
+
+/**
+ * Finds the offset of `needle` within the source text of `rule`.
+ */
+fn locate_in_rule(
+    table_definitions: &str,
+    table: &TableDefinition,
+    rule: &Rule,
+    needle: &str,
+) -> Option<usize> {
+    let rule_offset = table.offset + table_definitions.get(table.offset..)?.find(&rule.raw)?;
+
+    Some(rule_offset + rule.raw.find(needle)?)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     #[test]
-    fn test_roll_dice() {
-        for _ in 0..10000 {
-            let roll = roll_dice(1, 6);
-            assert!(roll >= 1);
-            assert!(roll <= 6);
+    fn test_seeded_generation_is_deterministic() {
+        let collection = TableCollection::parse(
+            "---
+id: color
+title: Colors
+---
+1: Red {1d6}
+2: Green {2d20}
+3: Blue {shade}
+
+---
+id: shade
+title: Shades
+---
+1: light
+1: dark
+1: pale",
+        )
+        .unwrap();
+
+        let a = collection.gen_many_with("color", 20, &mut GenContext::new(Some(42)));
+        let b = collection.gen_many_with("color", 20, &mut GenContext::new(Some(42)));
+        let c = collection.gen_many_with("color", 20, &mut GenContext::new(Some(43)));
+
+        assert!(a.is_ok());
+        assert_eq!(a.as_ref().unwrap(), b.as_ref().unwrap());
+        assert_ne!(a.unwrap(), c.unwrap());
+    }
+
+    #[test]
+    fn test_gen_ranged_table() {
+        let collection = TableCollection::parse(
+            "---
+id: reaction
+title: Reaction
+roll: 2d6
+---
+2-5: Hostile
+6-8: Neutral
+9-12: Friendly",
+        )
+        .unwrap();
+
+        for seed in 0..20 {
+            let traced = collection
+                .gen_traced_with("reaction", &mut GenContext::new(Some(seed)))
+                .unwrap();
+
+            let Some(TraceStep::Dice { total, .. }) = traced.trace.steps.first() else {
+                panic!("Expected a dice roll, got {:?}", traced.trace.steps);
+            };
+
+            let expected = match total {
+                2..=5 => "Hostile",
+                6..=8 => "Neutral",
+                _ => "Friendly",
+            };
+
+            assert_eq!(traced.result, expected);
+        }
+    }
+
+    #[test]
+    fn test_variables() {
+        let collection = TableCollection::parse(
+            "---
+id: hero
+title: Heroes
+---
+1: {first-name>$hero} the {class}. {$hero} has a {pet}
+
+---
+id: pet
+title: Pets
+---
+1: cat named after {$hero}
+
+---
+id: first-name
+title: First Names
+---
+1: Ada
+1: Brin
+1: Cato
+
+---
+id: class
+title: Classes
+---
+1: bard
+1: rogue",
+        )
+        .unwrap();
+
+        let results = collection
+            .gen_many_with("hero", 20, &mut GenContext::new(Some(3)))
+            .unwrap();
+
+        for result in results {
+            let name = result.split(' ').next().unwrap();
+
+            assert!(result.contains(&format!(". {} has a cat named after {}", name, name)));
+        }
+
+        // the variable is bound by `hero`, so this only fails when generating
+        // from `pet` directly
+        assert!(matches!(
+            collection.gen_with("pet", &mut GenContext::new(None)),
+            Err(TableError::UnboundVariableError(name)) if name == "hero"
+        ));
+    }
+
+    #[test]
+    fn test_unknown_variable() {
+        let source = "---
+id: hero
+title: Heroes
+---
+1: {first-name>$hero} and {$villain}
+
+---
+id: first-name
+title: First Names
+---
+1: Ada";
+
+        let diagnostics = TableCollection::diagnostics_for(source);
+
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].line, 5);
+        assert_eq!(
+            &source[diagnostics[0].offset..diagnostics[0].end_offset],
+            "$villain}"
+        );
+        assert!(TableCollection::parse(source).is_err());
+    }
+
+    #[test]
+    fn test_deck_mode() {
+        let source = "---
+id: tarot
+title: Tarot
+mode: deck
+---
+1: The Fool
+1: The Tower
+1: The Star";
+
+        let collection = TableCollection::parse(source).unwrap();
+        let mut ctx = GenContext::new(Some(3));
+
+        let mut drawn = collection.gen_many_with("tarot", 3, &mut ctx).unwrap();
+        drawn.sort();
+
+        assert_eq!(drawn, vec!["The Fool", "The Star", "The Tower"]);
+        assert!(collection.remaining_cards("tarot").unwrap().is_empty());
+
+        // an empty deck is reshuffled before drawing
+        collection.gen_with("tarot", &mut ctx).unwrap();
+        assert_eq!(collection.remaining_cards("tarot").unwrap().len(), 2);
+
+        // restored decks carry on where they left off
+        let restored = TableCollection::parse(source).unwrap();
+        restored.restore_decks(collection.deck_state());
+
+        let first = collection.gen_with("tarot", &mut GenContext::new(Some(1)));
+        let second = restored.gen_with("tarot", &mut GenContext::new(Some(1)));
+
+        assert_eq!(first.unwrap(), second.unwrap());
+        assert_eq!(
+            collection.remaining_cards("tarot").unwrap()[0].raw,
+            restored.remaining_cards("tarot").unwrap()[0].raw
+        );
+
+        collection.reshuffle("tarot");
+        assert_eq!(collection.remaining_cards("tarot").unwrap().len(), 3);
+        assert!(collection.remaining_cards("missing").is_err());
+    }
+
+    #[test]
+    fn test_cooldown() {
+        let source = "---
+id: loot
+title: Loot
+cooldown: 3
+---
+1: a sword
+1: a shield
+2: a helmet
+1: a ring
+
+---
+id: coin
+title: Coin
+---
+1: heads
+1: tails";
+
+        let collection = TableCollection::parse(source).unwrap();
+        let mut ctx = GenContext::new(Some(8));
+        let loot = collection.gen_many_with("loot", 40, &mut ctx).unwrap();
+
+        // any 4 picks in a row hold every row
+        for window in loot.windows(4) {
+            let mut window = window.to_vec();
+            window.sort();
+            window.dedup();
+            assert_eq!(window.len(), 4, "{:?}", window);
+        }
+
+        // a cooldown passed to the call, covering every row but the oldest
+        let mut ctx = GenContext::new(Some(8)).with_cooldown(Some(5));
+        let coins = collection.gen_many_with("coin", 20, &mut ctx).unwrap();
+
+        assert!(coins.windows(2).all(|w| w[0] != w[1]), "{:?}", coins);
+
+        // restored picks carry on where they left off
+        let restored = TableCollection::parse(source).unwrap();
+        restored.restore_recent_picks(collection.recent_picks());
+
+        assert_eq!(
+            collection
+                .gen_many_with("loot", 8, &mut GenContext::new(Some(1)))
+                .unwrap(),
+            restored
+                .gen_many_with("loot", 8, &mut GenContext::new(Some(1)))
+                .unwrap()
+        );
+
+        let result =
+            collection.gen_with("coin", &mut GenContext::new(None).with_cooldown(Some(101)));
+        assert!(matches!(result, Err(TableError::CallError(e)) if e.contains("too long")));
+    }
+
+    #[test]
+    fn test_cooldown_unique() {
+        let collection = TableCollection::parse(
+            "---
+id: pockets
+title: Pockets
+---
+1: {trinket|unique(2)|join(', ')}
+
+---
+id: trinket
+title: Trinkets
+cooldown: 2
+---
+1: a button
+1: a coin
+1: a key
+1: a marble",
+        )
+        .unwrap();
+
+        for seed in 0..10 {
+            let mut ctx = GenContext::new(Some(seed));
+            let pockets = collection.gen_with("pockets", &mut ctx).unwrap();
+            let trinket = collection.gen_with("trinket", &mut ctx).unwrap();
+
+            // `unique` picks like any other roll, so they're on cooldown
+            assert!(!pockets.contains(&trinket), "{} then {}", pockets, trinket);
+        }
+
+        assert_eq!(collection.recent_picks().picked["trinket"].len(), 30);
+    }
+
+    #[test]
+    fn test_unique_without_replacement() {
+        let collection = TableCollection::parse(
+            "---
+id: hoard
+title: Hoards
+---
+1: {gem|unique(3)|join(', ')}
+
+---
+id: gem
+title: Gems
+---
+1000: ruby
+1000: opal
+1: diamond",
+        )
+        .unwrap();
+
+        for seed in 0..50 {
+            let result = collection
+                .gen_with("hoard", &mut GenContext::new(Some(seed)))
+                .unwrap();
+            let mut gems: Vec<&str> = result.split(", ").collect();
+            gems.sort();
+
+            assert_eq!(gems, vec!["diamond", "opal", "ruby"]);
+        }
+    }
+
+    #[test]
+    fn test_unique_overflow() {
+        let cases = [
+            (
+                "1: {color|unique(1d4)}",
+                "`unique(1d4)` can ask for 4 distinct results, but there are only 3",
+            ),
+            (
+                "1: {color|lower|unique(3)}",
+                "`unique(3)` can ask for 3 distinct results, but there are only 2",
+            ),
+            (
+                "1: {[a|b|{color}]|unique(6)}",
+                "`unique(6)` can ask for 6 distinct results, but there are only 5",
+            ),
+        ];
+
+        for (rule, message) in cases {
+            let source = format!(
+                "---\nid: t\ntitle: T\n---\n{}\n\n---\nid: color\ntitle: Colors\n---\n1: Red\n1: red\n1: Blue",
+                rule
+            );
+            let diagnostics = TableCollection::diagnostics_for(&source);
+
+            assert_eq!(diagnostics.len(), 1, "{}", rule);
+            assert_eq!(diagnostics[0].message, message);
+            assert!(source[diagnostics[0].offset..].starts_with("unique("));
+        }
+
+        assert!(TableCollection::diagnostics_for(
+            "---\nid: t\ntitle: T\n---\n1: {[a|b|c]|unique(1d3)}"
+        )
+        .is_empty());
+    }
+
+    #[test]
+    fn test_inline_choices() {
+        let collection = TableCollection::parse(
+            "---
+id: outfit
+title: Outfits
+---
+1: {[3:red|1:{shade} blue]|capitalize} hat, {[green|gold|grey]|unique(2)|join(', ')} boots
+
+---
+id: shade
+title: Shades
+---
+1: light",
+        )
+        .unwrap();
+
+        for seed in 0..20 {
+            let result = collection
+                .gen_with("outfit", &mut GenContext::new(Some(seed)))
+                .unwrap();
+            let (hat, boots) = result.split_once(" hat, ").unwrap();
+            let boots: Vec<&str> = boots.trim_end_matches(" boots").split(", ").collect();
+
+            assert!(hat == "Red" || hat == "Light blue", "{}", result);
+            assert_eq!(boots.len(), 2);
+            assert_ne!(boots[0], boots[1]);
+            assert!(boots.iter().all(|b| ["green", "gold", "grey"].contains(b)));
+        }
+
+        let outcomes = collection.enumerate("outfit").unwrap().outcomes.unwrap();
+        let red: f64 = outcomes
+            .iter()
+            .filter(|o| o.text.starts_with("Red"))
+            .map(|o| o.probability)
+            .sum();
+
+        assert_eq!(outcomes.len(), 12);
+        assert!((red - 0.75).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_conditionals() {
+        let collection = TableCollection::parse(
+            "---
+id: npc
+title: NPCs
+---
+1: {race>$race} {if $race == Elf: with pointy ears|else: with round ears}{25%: , scarred}
+
+---
+id: race
+title: Races
+---
+1: Elf
+1: Dwarf",
+        )
+        .unwrap();
+
+        for seed in 0..20 {
+            let result = collection
+                .gen_with("npc", &mut GenContext::new(Some(seed)))
+                .unwrap();
+
+            assert!(
+                matches!(
+                    result.strip_suffix(", scarred").unwrap_or(&result),
+                    "Elf with pointy ears" | "Dwarf with round ears"
+                ),
+                "{}",
+                result
+            );
+        }
+
+        let outcomes = collection.analyze("npc").unwrap().rules[0].outcomes.clone();
+        assert_eq!(outcomes, None);
+
+        let chance = TableCollection::parse(
+            "---
+id: scar
+title: Scars
+---
+1: {25%: scarred|else: unscarred}",
+        )
+        .unwrap();
+
+        let outcomes = chance.analyze("scar").unwrap().rules[0].outcomes.clone();
+        let outcomes: Vec<(String, f64)> = outcomes
+            .unwrap()
+            .into_iter()
+            .map(|o| (o.text, o.probability))
+            .collect();
+
+        assert_eq!(
+            outcomes,
+            vec![
+                ("unscarred".to_string(), 0.75),
+                ("scarred".to_string(), 0.25)
+            ]
+        );
+
+        let unbound = TableCollection::parse(
+            "---
+id: npc
+title: NPCs
+---
+1: {if $race == Elf: pointy ears}",
+        );
+
+        assert!(unbound.is_err());
+    }
+
+    #[test]
+    fn test_repeat() {
+        let collection = TableCollection::parse(
+            "---
+id: goblins
+title: Goblins
+---
+1: goblins carrying {weapon|repeat(1d4)|join(', ', ' and ')}
+
+---
+id: weapon
+title: Weapons
+---
+1: a spear
+1: a club",
+        )
+        .unwrap();
+
+        for seed in 0..20 {
+            let traced = collection
+                .gen_traced_with("goblins", &mut GenContext::new(Some(seed)))
+                .unwrap();
+
+            let Some(TraceStep::Dice { total, .. }) = traced.trace.steps.first() else {
+                panic!("Expected a dice roll, got {:?}", traced.trace.steps);
+            };
+
+            let weapons =
+                traced.result.matches("a spear").count() + traced.result.matches("a club").count();
+
+            assert_eq!(weapons as i64, *total);
+        }
+
+        let enumeration = collection.enumerate("goblins").unwrap();
+        let outcomes = enumeration.outcomes.unwrap();
+
+        assert_eq!(enumeration.size_estimate, 2.0 + 4.0 + 8.0 + 16.0);
+        assert_eq!(outcomes.len(), 30);
+        assert!(outcomes
+            .iter()
+            .any(|o| o.text == "goblins carrying a club, a club and a club"));
+
+        // dice counts are checked when rolled
+        let horde = TableCollection::parse(
+            "---
+id: horde
+title: Horde
+---
+1: {goblin|repeat(999+1d1000000)}
+
+---
+id: goblin
+title: Goblin
+---
+1: a goblin",
+        )
+        .unwrap();
+
+        let result = horde.gen_with("horde", &mut GenContext::new(Some(1)));
+
+        assert!(matches!(result, Err(TableError::CallError(e)) if e.contains("at most 1000")));
+    }
+
+    #[test]
+    fn test_text_filters() {
+        let collection = TableCollection::parse(
+            "---
+id: title
+title: Titles
+---
+1: {name|trim|title|possessive} {1d1+41|words} and the {1d1+20|ordinal} {1d1+13|roman|quote}
+
+---
+id: name
+title: Names
+---
+1: the lord of  the rings ",
+        )
+        .unwrap();
+
+        let result = collection
+            .gen_with("title", &mut GenContext::new(Some(0)))
+            .unwrap();
+
+        assert_eq!(
+            result,
+            "The Lord of the Rings' forty-two and the 21st \"XIV\""
+        );
+    }
+
+    #[test]
+    fn test_filters_after_join() {
+        let collection = TableCollection::parse(
+            "---
+id: pets
+title: Pets
+---
+1: {pet|indefinite|unique(2)|join(' and ')|capitalize}
+
+---
+id: pet
+title: Pet
+---
+1: cat
+1: dog",
+        )
+        .unwrap();
+
+        for seed in 0..10 {
+            let result = collection
+                .gen_with("pets", &mut GenContext::new(Some(seed)))
+                .unwrap();
+
+            assert!(
+                result == "A cat and a dog" || result == "A dog and a cat",
+                "{}",
+                result
+            );
+        }
+    }
+
+    #[test]
+    fn test_custom_filters() {
+        let source = "---
+id: loot
+title: Loot
+---
+1: {1d1+299|gold} and {[a ruby|an opal]|shout}";
+
+        // unknown names are syntax errors, so typos are caught
+        assert_eq!(
+            TableCollection::diagnostics_for(source)
+                .iter()
+                .map(|d| (d.message.as_str(), d.severity, d.column))
+                .collect::<Vec<_>>(),
+            vec![("Unknown filter `gold`", Severity::Error, 13)]
+        );
+
+        let mut filters = CustomFilters::default();
+
+        filters
+            .register("gold", |copper: &str| {
+                format!("{} gp", copper.parse::<i64>().unwrap_or(0) / 100)
+            })
+            .unwrap();
+        filters
+            .register("shout", |value: &str| format!("{}!", value.to_uppercase()))
+            .unwrap();
+
+        let collection = TableCollection::parse_with_filters(source, filters.clone()).unwrap();
+        let result = collection
+            .gen_with("loot", &mut GenContext::new(Some(0)))
+            .unwrap();
+
+        assert!(
+            result == "3 gp and A RUBY!" || result == "3 gp and AN OPAL!",
+            "{}",
+            result
+        );
+
+        // the names are only known while parsing with them
+        assert!(TableCollection::parse(source).is_err());
+
+        for name in ["upper", "unique", "to gold", "2x", ""] {
+            assert!(
+                filters.register(name, |v: &str| v.to_string()).is_err(),
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn test_inflection_filters() {
+        let collection = TableCollection::parse(
+            "---
+id: encounter
+title: Encounters
+plurals: cyclops/cyclopes
+---
+1: {1d3>$n} {monster|count($n)}, {monster|plural} and a {[wolves|knives]|singular}
+
+---
+id: monster
+title: Monsters
+---
+1: cyclops",
+        )
+        .unwrap();
+
+        for seed in 0..10 {
+            let result = collection
+                .gen_with("encounter", &mut GenContext::new(Some(seed)))
+                .unwrap();
+
+            let expected = [
+                "1 cyclops, cyclopes and a ",
+                "2 cyclopes, cyclopes and a ",
+                "3 cyclopes, cyclopes and a ",
+            ];
+
+            assert!(expected.iter().any(|e| result.starts_with(e)), "{}", result);
+            assert!(
+                result.ends_with("wolf") || result.ends_with("knife"),
+                "{}",
+                result
+            );
         }
 
-        for _ in 0..10000 {
-            let roll = roll_dice(5, 10);
-            assert!(roll >= 5);
-            assert!(roll <= 50);
+        let articles = TableCollection::parse(
+            "---
+id: omen
+title: Omens
+articles: a historic
+---
+1: {[hour|unicorn|Elf|historic moment|8-foot pole]|indefinite|unique(5)|join(', ')}",
+        )
+        .unwrap()
+        .gen_with("omen", &mut GenContext::new(Some(1)))
+        .unwrap();
+
+        for expected in [
+            "an hour",
+            "a unicorn",
+            "an Elf",
+            "a historic moment",
+            "an 8-foot pole",
+        ] {
+            assert!(articles.contains(expected), "{}", articles);
         }
+
+        let unbound = TableCollection::parse(
+            "---
+id: encounter
+title: Encounters
+---
+1: {monster|count($n)}
+
+---
+id: monster
+title: Monsters
+---
+1: orc",
+        );
+
+        assert!(unbound.is_err());
+    }
+
+    #[test]
+    fn test_find_cycles() {
+        let source = "---
+id: a
+title: A
+---
+1: {b}
+
+---
+id: b
+title: B
+---
+1: {a} and {c}
+1: {b}
+
+---
+id: c
+title: C
+---
+1: {c}
+1: done
+
+---
+id: d
+title: D
+---
+1: {a}";
+
+        let collection = TableCollection::parse_recovering(source);
+
+        assert_eq!(
+            collection.find_cycles(),
+            vec![vec!["a".to_string(), "b".to_string(), "a".to_string()]]
+        );
+        assert_eq!(collection.diagnostics().len(), 1);
+        assert_eq!(collection.diagnostics()[0].line, 2);
+        assert!(matches!(
+            TableCollection::parse(source),
+            Err(TableError::SyntaxError(_))
+        ));
+    }
+
+    #[test]
+    fn test_max_depth() {
+        let mut collection = TableCollection::parse_recovering(
+            "---
+id: a
+title: A
+---
+1: x{a}",
+        );
+
+        assert!(matches!(
+            collection.gen_with("a", &mut GenContext::new(None)),
+            Err(TableError::DepthLimitError(100))
+        ));
+
+        collection.set_max_depth(3);
+
+        assert!(matches!(
+            collection.gen_with("a", &mut GenContext::new(None)),
+            Err(TableError::DepthLimitError(3))
+        ));
+
+        // rows drawn without replacement count towards the depth too
+        let mut collection = TableCollection::parse(
+            "---
+id: parent
+title: Parent
+---
+1: {child|unique(2)}
+
+---
+id: child
+title: Child
+---
+1: x
+1: y",
+        )
+        .unwrap();
+
+        collection.set_max_depth(1);
+
+        assert!(matches!(
+            collection.gen_with("parent", &mut GenContext::new(None)),
+            Err(TableError::DepthLimitError(1))
+        ));
     }
 
     #[test]
@@ -449,6 +2696,7 @@ mod tests {
                 vec![Rule {
                     raw: "parent {child}".to_string(),
                     weight: 1.0,
+                    range: None,
                     parts: vec![
                         RuleInst::Literal("parent ".to_string()),
                         RuleInst::Interpolation("child".to_string(), vec![]),
@@ -467,6 +2715,7 @@ mod tests {
                 vec![Rule {
                     raw: "child text".to_string(),
                     weight: 1.0,
+                    range: None,
                     parts: vec![RuleInst::Literal("child text".to_string())],
                 }],
             ),
@@ -474,10 +2723,10 @@ mod tests {
 
         let collection = TableCollection {
             table_map,
-            external_identifiers: vec![],
+            ..Default::default()
         };
 
-        let result = collection._gen("parent", false);
+        let result = collection._gen("parent", false, &mut GenContext::new(None));
 
         assert!(result.is_ok());
 
@@ -500,9 +2749,13 @@ mod tests {
                 vec![Rule {
                     raw: "parent {child|unique(2)}".to_string(),
                     weight: 1.0,
+                    range: None,
                     parts: vec![
                         RuleInst::Literal("parent ".to_string()),
-                        RuleInst::Interpolation("child".to_string(), vec![FilterOp::Unique(2)]),
+                        RuleInst::Interpolation(
+                            "child".to_string(),
+                            vec![FilterOp::Unique(DiceExpr::Number(2))],
+                        ),
                     ],
                 }],
             ),
@@ -519,11 +2772,13 @@ mod tests {
                     Rule {
                         raw: "child 1".to_string(),
                         weight: 1.0,
+                        range: None,
                         parts: vec![RuleInst::Literal("child 1".to_string())],
                     },
                     Rule {
                         raw: "child 2".to_string(),
                         weight: 1.0,
+                        range: None,
                         parts: vec![RuleInst::Literal("child 2".to_string())],
                     },
                 ],
@@ -532,10 +2787,10 @@ mod tests {
 
         let collection = TableCollection {
             table_map,
-            external_identifiers: vec![],
+            ..Default::default()
         };
 
-        let result = collection._gen("parent", false);
+        let result = collection._gen("parent", false, &mut GenContext::new(None));
 
         assert!(result.is_ok());
 
@@ -558,9 +2813,13 @@ mod tests {
                 vec![Rule {
                     raw: "parent {child|unique(2)}".to_string(),
                     weight: 1.0,
+                    range: None,
                     parts: vec![
                         RuleInst::Literal("parent ".to_string()),
-                        RuleInst::Interpolation("child".to_string(), vec![FilterOp::Unique(2)]),
+                        RuleInst::Interpolation(
+                            "child".to_string(),
+                            vec![FilterOp::Unique(DiceExpr::Number(2))],
+                        ),
                     ],
                 }],
             ),
@@ -576,6 +2835,7 @@ mod tests {
                 vec![Rule {
                     raw: "child 1".to_string(),
                     weight: 1.0,
+                    range: None,
                     parts: vec![RuleInst::Literal("child 1".to_string())],
                 }],
             ),
@@ -583,15 +2843,18 @@ mod tests {
 
         let collection = TableCollection {
             table_map,
-            external_identifiers: vec![],
+            ..Default::default()
         };
 
-        let result = collection._gen("parent", false);
+        let result = collection._gen("parent", false, &mut GenContext::new(None));
 
         assert!(result.is_err());
 
         if let Err(TableError::CallError(msg)) = result {
-            assert_eq!(msg, "Failed to generate unique result for rule parent {child|unique(2)} after 20 attempts");
+            assert_eq!(
+                msg,
+                "Not enough distinct results for `unique` in rule parent {child|unique(2)}"
+            );
         } else {
             panic!("Unexpected result: {:?}", result);
         }
@@ -611,6 +2874,7 @@ mod tests {
                 vec![Rule {
                     raw: "parent {child|indefinite|capitalize|unique(2)|join(', ')}".to_string(),
                     weight: 1.0,
+                    range: None,
                     parts: vec![
                         RuleInst::Literal("parent ".to_string()),
                         RuleInst::Interpolation(
@@ -618,7 +2882,7 @@ mod tests {
                             vec![
                                 FilterOp::IndefiniteArticle,
                                 FilterOp::Capitalize,
-                                FilterOp::Unique(2),
+                                FilterOp::Unique(DiceExpr::Number(2)),
                                 FilterOp::Join(", ".to_string(), None),
                             ],
                         ),
@@ -638,11 +2902,13 @@ mod tests {
                     Rule {
                         raw: "child 1".to_string(),
                         weight: 1.0,
+                        range: None,
                         parts: vec![RuleInst::Literal("child 1".to_string())],
                     },
                     Rule {
                         raw: "child 2".to_string(),
                         weight: 1.0,
+                        range: None,
                         parts: vec![RuleInst::Literal("child 2".to_string())],
                     },
                 ],
@@ -651,10 +2917,10 @@ mod tests {
 
         let collection = TableCollection {
             table_map,
-            external_identifiers: vec![],
+            ..Default::default()
         };
 
-        let result = collection._gen("parent", false);
+        let result = collection._gen("parent", false, &mut GenContext::new(None));
 
         assert!(result.is_ok());
 
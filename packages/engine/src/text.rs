@@ -0,0 +1,275 @@
+// words that stay lowercase in titles unless they're the first or last word
+static SMALL_WORDS: &[&str] = &[
+    "a", "an", "and", "as", "at", "but", "by", "for", "from", "in", "into", "nor", "of", "off",
+    "on", "or", "over", "per", "so", "the", "to", "up", "via", "with", "yet",
+];
+
+static ONES: &[&str] = &[
+    "zero",
+    "one",
+    "two",
+    "three",
+    "four",
+    "five",
+    "six",
+    "seven",
+    "eight",
+    "nine",
+    "ten",
+    "eleven",
+    "twelve",
+    "thirteen",
+    "fourteen",
+    "fifteen",
+    "sixteen",
+    "seventeen",
+    "eighteen",
+    "nineteen",
+];
+
+static TENS: &[&str] = &[
+    "", "", "twenty", "thirty", "forty", "fifty", "sixty", "seventy", "eighty", "ninety",
+];
+
+static SCALES: &[&str] = &[
+    "",
+    "thousand",
+    "million",
+    "billion",
+    "trillion",
+    "quadrillion",
+    "quintillion",
+];
+
+pub fn capitalize(value: &str) -> String {
+    let mut chars = value.chars();
+
+    match chars.next() {
+        Some(first) => format!("{}{}", first.to_uppercase(), chars.as_str()),
+        None => String::new(),
+    }
+}
+
+/**
+ * Capitalizes every word except small words such as `of` and `the` in the
+ * middle, e.g. `the sword of the north` -> `The Sword of the North`.
+ */
+pub fn title_case(value: &str) -> String {
+    let words: Vec<&str> = value.split(' ').collect();
+    let last = words.len().saturating_sub(1);
+
+    words
+        .iter()
+        .enumerate()
+        .map(|(i, word)| {
+            if i != 0 && i != last && SMALL_WORDS.contains(&word.to_lowercase().as_str()) {
+                word.to_lowercase()
+            } else {
+                capitalize(word)
+            }
+        })
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+pub fn sentence_case(value: &str) -> String {
+    capitalize(&value.to_lowercase())
+}
+
+/**
+ * Removes whitespace from both ends and collapses runs of spaces, e.g. left
+ * behind by an empty conditional.
+ */
+pub fn trim(value: &str) -> String {
+    value.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+pub fn possessive(value: &str) -> String {
+    if value.ends_with(['s', 'S']) {
+        format!("{}'", value)
+    } else {
+        format!("{}'s", value)
+    }
+}
+
+/**
+ * `1` -> `1st`, `12` -> `12th`, `22` -> `22nd`. Anything other than an
+ * integer is left as is.
+ */
+pub fn ordinal(value: &str) -> String {
+    let Some(n) = parse_integer(value) else {
+        return value.to_string();
+    };
+
+    let suffix = match (n.unsigned_abs() % 10, n.unsigned_abs() % 100) {
+        (_, 11..=13) => "th",
+        (1, _) => "st",
+        (2, _) => "nd",
+        (3, _) => "rd",
+        _ => "th",
+    };
+
+    format!("{}{}", n, suffix)
+}
+
+/**
+ * `42` -> `forty-two`. Anything other than an integer is left as is.
+ */
+pub fn words(value: &str) -> String {
+    match parse_integer(value) {
+        Some(n) if n < 0 => format!("minus {}", number_words(n.unsigned_abs())),
+        Some(n) => number_words(n as u64),
+        None => value.to_string(),
+    }
+}
+
+/**
+ * `14` -> `XIV`. Only numbers from 1 to 3999 have a roman numeral, anything
+ * else is left as is.
+ */
+pub fn roman(value: &str) -> String {
+    let Some(mut n) = parse_integer(value).filter(|n| (1..=3999).contains(n)) else {
+        return value.to_string();
+    };
+
+    let numerals = [
+        (1000, "M"),
+        (900, "CM"),
+        (500, "D"),
+        (400, "CD"),
+        (100, "C"),
+        (90, "XC"),
+        (50, "L"),
+        (40, "XL"),
+        (10, "X"),
+        (9, "IX"),
+        (5, "V"),
+        (4, "IV"),
+        (1, "I"),
+    ];
+
+    let mut result = String::new();
+
+    for (amount, numeral) in numerals {
+        while n >= amount {
+            result.push_str(numeral);
+            n -= amount;
+        }
+    }
+
+    result
+}
+
+fn parse_integer(value: &str) -> Option<i64> {
+    value.trim().parse().ok()
+}
+
+fn number_words(n: u64) -> String {
+    if n == 0 {
+        return ONES[0].to_string();
+    }
+
+    let mut groups = vec![];
+    let mut rest = n;
+
+    for scale in SCALES {
+        let group = rest % 1000;
+        rest /= 1000;
+
+        if group > 0 {
+            groups.push(match *scale {
+                "" => hundreds_words(group),
+                scale => format!("{} {}", hundreds_words(group), scale),
+            });
+        }
+
+        if rest == 0 {
+            break;
+        }
+    }
+
+    groups.reverse();
+    groups.join(" ")
+}
+
+// words for a number below 1000
+fn hundreds_words(n: u64) -> String {
+    let (hundreds, rest) = (n / 100, (n % 100) as usize);
+
+    let rest = match rest {
+        0 => None,
+        1..=19 => Some(ONES[rest].to_string()),
+        _ if rest % 10 == 0 => Some(TENS[rest / 10].to_string()),
+        _ => Some(format!("{}-{}", TENS[rest / 10], ONES[rest % 10])),
+    };
+
+    match (hundreds, rest) {
+        (0, Some(rest)) => rest,
+        (hundreds, None) => format!("{} hundred", ONES[hundreds as usize]),
+        (hundreds, Some(rest)) => format!("{} hundred and {}", ONES[hundreds as usize], rest),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_case_transforms() {
+        assert_eq!(
+            title_case("the sword of the north"),
+            "The Sword of the North"
+        );
+        assert_eq!(title_case("what the dwarf sat on"), "What the Dwarf Sat On");
+        assert_eq!(sentence_case("a BRAVE knight"), "A brave knight");
+        assert_eq!(trim("  a   red  hat "), "a red hat");
+        assert_eq!(possessive("James"), "James'");
+        assert_eq!(possessive("dog"), "dog's");
+    }
+
+    #[test]
+    fn test_number_transforms() {
+        let ordinals = [
+            ("1", "1st"),
+            ("2", "2nd"),
+            ("3", "3rd"),
+            ("4", "4th"),
+            ("11", "11th"),
+            ("12", "12th"),
+            ("13", "13th"),
+            ("21", "21st"),
+            ("112", "112th"),
+            ("-1", "-1st"),
+            ("many", "many"),
+        ];
+
+        for (n, expected) in ordinals {
+            assert_eq!(ordinal(n), expected);
+        }
+
+        let words_cases = [
+            ("0", "zero"),
+            ("7", "seven"),
+            ("42", "forty-two"),
+            ("80", "eighty"),
+            ("100", "one hundred"),
+            ("118", "one hundred and eighteen"),
+            ("2024", "two thousand twenty-four"),
+            ("1000001", "one million one"),
+            ("-3", "minus three"),
+        ];
+
+        for (n, expected) in words_cases {
+            assert_eq!(words(n), expected);
+        }
+
+        assert_eq!(
+            words(&i64::MIN.to_string()).split(' ').next(),
+            Some("minus")
+        );
+        assert_eq!(roman("14"), "XIV");
+        assert_eq!(roman("1994"), "MCMXCIV");
+        assert_eq!(roman("0"), "0");
+        assert_eq!(roman("4000"), "4000");
+    }
+}
@@ -0,0 +1,209 @@
+use serde::Serialize;
+
+use crate::dice::RolledDice;
+use crate::table_collection::{FilterOp, Rule};
+
+/**
+ * A generated result along with the trace explaining how it was produced.
+ */
+#[derive(Debug, Clone, Serialize)]
+pub struct TracedResult {
+    pub result: String,
+    pub trace: TraceNode,
+}
+
+/**
+ * One table roll: which rule was picked and everything that happened while
+ * resolving it, in rule order.
+ */
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TraceNode {
+    pub table_id: String,
+    pub rule_index: usize,
+    pub raw: String,
+    pub weight: f32,
+    pub steps: Vec<TraceStep>,
+    pub result: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum TraceStep {
+    Dice {
+        expression: String,
+        rolls: Vec<RolledDice>,
+        total: i64,
+    },
+    Interpolation {
+        table_id: String,
+        filters: Vec<FilterOp>,
+        // every table roll made, including ones discarded by `unique`
+        rolls: Vec<TraceNode>,
+        unique_retries: usize,
+        result: String,
+    },
+    // whether the text of a conditional was included
+    Condition {
+        condition: String,
+        passed: bool,
+    },
+    // an inline choice, each roll is an option picked with an empty
+    // `table_id`
+    Choice {
+        filters: Vec<FilterOp>,
+        rolls: Vec<TraceNode>,
+        unique_retries: usize,
+        result: String,
+    },
+}
+
+/**
+ * Builds the trace tree while generating. Table rolls are pushed onto a
+ * stack as they start and attached to the enclosing interpolation once they
+ * finish, so the tree mirrors the recursion of `Rule::resolve`.
+ */
+#[derive(Debug, Clone, Default)]
+pub struct Tracer {
+    stack: Vec<TraceNode>,
+    roots: Vec<TraceNode>,
+}
+
+impl Tracer {
+    pub fn enter_table(&mut self, table_id: &str, rule_index: usize, rule: &Rule) {
+        self.stack.push(TraceNode {
+            table_id: table_id.to_string(),
+            rule_index,
+            raw: rule.raw.clone(),
+            weight: rule.weight,
+            steps: vec![],
+            result: String::new(),
+        });
+    }
+
+    pub fn exit_table(&mut self, result: &str) {
+        let Some(mut node) = self.stack.pop() else {
+            return;
+        };
+
+        node.result = result.to_string();
+
+        match self.current_interpolation() {
+            Some(TraceStep::Interpolation { rolls, .. } | TraceStep::Choice { rolls, .. }) => {
+                rolls.push(node)
+            }
+            _ => self.roots.push(node),
+        }
+    }
+
+    pub fn record(&mut self, step: TraceStep) {
+        if let Some(node) = self.stack.last_mut() {
+            node.steps.push(step);
+        }
+    }
+
+    pub fn exit_interpolation(&mut self, retries: usize, output: &str) {
+        if let Some(
+            TraceStep::Interpolation {
+                unique_retries,
+                result,
+                ..
+            }
+            | TraceStep::Choice {
+                unique_retries,
+                result,
+                ..
+            },
+        ) = self.current_interpolation()
+        {
+            *unique_retries = retries;
+            *result = output.to_string();
+        }
+    }
+
+    /**
+     * Removes and returns the most recently completed top-level table roll.
+     */
+    pub fn take(&mut self) -> Option<TraceNode> {
+        self.stack.clear();
+        self.roots.pop()
+    }
+
+    fn current_interpolation(&mut self) -> Option<&mut TraceStep> {
+        self.stack
+            .last_mut()
+            .and_then(|node| node.steps.last_mut())
+            .filter(|step| {
+                matches!(
+                    step,
+                    TraceStep::Interpolation { .. } | TraceStep::Choice { .. }
+                )
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::table_collection::{GenContext, TableCollection};
+
+    #[test]
+    fn test_gen_traced() {
+        let collection = TableCollection::parse(
+            "---
+id: color
+title: Colors
+---
+1: {shade|unique(2)|join(', ')} Red {2d6}
+
+---
+id: shade
+title: Shades
+---
+1: light
+1: dark",
+        )
+        .unwrap();
+
+        let traced = collection
+            .gen_traced_with("color", &mut GenContext::new(Some(7)))
+            .unwrap();
+
+        let trace = traced.trace;
+        assert_eq!(trace.table_id, "color");
+        assert_eq!(trace.rule_index, 0);
+        assert_eq!(trace.raw, "{shade|unique(2)|join(', ')} Red {2d6}");
+        assert_eq!(trace.result, traced.result);
+        assert_eq!(trace.steps.len(), 2);
+
+        if let TraceStep::Interpolation {
+            table_id,
+            rolls,
+            unique_retries,
+            result,
+            ..
+        } = &trace.steps[0]
+        {
+            assert_eq!(table_id, "shade");
+            assert_eq!(rolls.len(), 2 + unique_retries);
+            assert!(result == "light, dark" || result == "dark, light");
+        } else {
+            panic!("Unexpected step: {:?}", trace.steps[0]);
+        }
+
+        if let TraceStep::Dice {
+            expression,
+            rolls,
+            total,
+        } = &trace.steps[1]
+        {
+            let faces = &rolls[0].faces;
+
+            assert_eq!(expression, "2d6");
+            assert_eq!(faces.len(), 2);
+            assert_eq!(faces.iter().map(|f| f.value as i64).sum::<i64>(), *total);
+            assert!(traced.result.ends_with(&format!(" Red {}", total)));
+        } else {
+            panic!("Unexpected step: {:?}", trace.steps[1]);
+        }
+    }
+}
@@ -11,4 +11,76 @@ export type TableMetadata = {
   title: string;
   export: boolean;
   namespace: string | undefined;
+  mode: "random" | "deck";
+  cooldown: number;
+  metadata: Map<string, string>;
+};
+
+export type Diagnostic = {
+  severity: "error" | "warning";
+  message: string;
+  offset: number;
+  line: number;
+  column: number;
+  end_offset: number;
+  end_line: number;
+  end_column: number;
+  context: string[];
+};
+
+export type DeckState = {
+  drawn: Record<string, number[]>;
+};
+
+export type HistoryEntry = {
+  table_id: string;
+  result: string;
+};
+
+export type TraceStep =
+  | {
+      kind: "dice";
+      expression: string;
+      rolls: RolledDice[];
+      total: number;
+    }
+  | {
+      kind: "interpolation";
+      table_id: string;
+      filters: unknown[];
+      rolls: TraceNode[];
+      unique_retries: number;
+      result: string;
+    }
+  | {
+      kind: "condition";
+      condition: string;
+      passed: boolean;
+    }
+  | {
+      kind: "choice";
+      filters: unknown[];
+      rolls: TraceNode[];
+      unique_retries: number;
+      result: string;
+    };
+
+export type RolledDice = {
+  notation: string;
+  faces: { value: number; kept: boolean }[];
+  total: number;
+};
+
+export type TraceNode = {
+  table_id: string;
+  rule_index: number;
+  raw: string;
+  weight: number;
+  steps: TraceStep[];
+  result: string;
+};
+
+export type TracedResult = {
+  result: string;
+  trace: TraceNode;
 };